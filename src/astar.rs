@@ -67,13 +67,6 @@ impl AStarContext
 		}
 	}
 
-	fn heuristic(&self, from: Point2<i32>, to: Point2<i32>) -> f32
-	{
-		let dx = (from.x - to.x) as f32;
-		let dy = (from.y - to.y) as f32;
-		(dx * dx + dy * dy).sqrt()
-	}
-
 	fn map_to_idx(&self, pos: Point2<i32>) -> Option<usize>
 	{
 		if pos.x < 0 || pos.y < 0 || pos.x >= self.size as i32 || pos.y >= self.size as i32
@@ -95,6 +88,34 @@ impl AStarContext
 	pub fn solve<S: Fn(Point2<i32>) -> bool, C: Fn(Point2<i32>) -> f32>(
 		&mut self, from: Point2<i32>, to: Point2<i32>, is_solid: S, cost_fn: C,
 	) -> Vec<Point2<i32>>
+	{
+		let size = self.size as i32;
+		self.solve_graph(
+			from,
+			to,
+			|pos| {
+				[(-1, 0), (1, 0), (0, -1), (0, 1)]
+					.iter()
+					.map(|&(dx, dy)| Point2::new(pos.x + dx, pos.y + dy))
+					.filter(|next| next.x >= 0 && next.y >= 0 && next.x < size && next.y < size)
+					.filter(|&next| !is_solid(next))
+					.map(|next| (next, 1. + cost_fn(next)))
+					.collect()
+			},
+			heuristic,
+		)
+	}
+
+	/// Like `solve`, but on an arbitrary graph over the grid nodes. `neighbours` returns the
+	/// nodes reachable from a node together with the cost of the step.
+	///
+	/// N.B. this returns the path in reverse order.
+	pub fn solve_graph<
+		N: Fn(Point2<i32>) -> Vec<(Point2<i32>, f32)>,
+		H: Fn(Point2<i32>, Point2<i32>) -> f32,
+	>(
+		&mut self, from: Point2<i32>, to: Point2<i32>, neighbours: N, heuristic: H,
+	) -> Vec<Point2<i32>>
 	{
 		self.open_set.clear();
 		for i in 0..self.came_from.len()
@@ -104,12 +125,12 @@ impl AStarContext
 		}
 
 		let from_idx = self.map_to_idx(from).unwrap();
-		self.cost[from_idx] = self.heuristic(from, from);
+		self.cost[from_idx] = heuristic(from, from);
 		self.came_from[from_idx] = from_idx as isize;
 		self.open_set
-			.push(NodeAndScore::new(from, self.heuristic(from, from)));
+			.push(NodeAndScore::new(from, heuristic(from, from)));
 
-		let mut best_score_so_far = self.heuristic(from, to);
+		let mut best_score_so_far = heuristic(from, to);
 		let mut best_idx_so_far = -1;
 
 		let to_idx = self.map_to_idx(to).unwrap();
@@ -138,20 +159,14 @@ impl AStarContext
 				}
 			}
 
-			for (dx, dy, cost) in &[(-1, 0, 1.), (1, 0, 1.), (0, -1, 1.), (0, 1, 1.)]
+			for (next, cost) in neighbours(cur.pos)
 			{
-				let next = Point2::new(cur.pos.x + dx, cur.pos.y + dy);
 				if let Some(next_idx) = self.map_to_idx(next)
 				{
-					if is_solid(next)
-					{
-						continue;
-					}
-
-					let new_cost = self.cost[cur_idx] + cost + cost_fn(next);
+					let new_cost = self.cost[cur_idx] + cost;
 					if new_cost < self.cost[next_idx]
 					{
-						let new_heuristic = self.heuristic(next, to);
+						let new_heuristic = heuristic(next, to);
 						if new_heuristic < best_score_so_far
 						{
 							best_score_so_far = new_heuristic;
@@ -191,3 +206,10 @@ impl AStarContext
 		}
 	}
 }
+
+fn heuristic(from: Point2<i32>, to: Point2<i32>) -> f32
+{
+	let dx = (from.x - to.x) as f32;
+	let dy = (from.y - to.y) as f32;
+	(dx * dx + dy * dy).sqrt()
+}
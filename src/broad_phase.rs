@@ -0,0 +1,160 @@
+use crate::components as comps;
+use nalgebra::Point2;
+use std::collections::BTreeMap;
+
+struct Entry<T>
+{
+	id: T,
+	pos: Point2<f32>,
+	size: f32,
+	kind: comps::CollideKind,
+}
+
+impl<T> Entry<T>
+{
+	fn min(&self) -> Point2<f32>
+	{
+		Point2::new(self.pos.x - self.size, self.pos.y - self.size)
+	}
+
+	fn max(&self) -> Point2<f32>
+	{
+		Point2::new(self.pos.x + self.size, self.pos.y + self.size)
+	}
+}
+
+/// Uniform grid of circular solids. Each solid is put into every grid cell its bounding box
+/// overlaps, so solids of any size are supported.
+pub struct BroadPhase<T>
+{
+	cell_size: f32,
+	entries: Vec<Entry<T>>,
+	cells: BTreeMap<(i32, i32), Vec<usize>>,
+}
+
+impl<T: Copy> BroadPhase<T>
+{
+	pub fn new(cell_size: f32) -> Self
+	{
+		Self {
+			cell_size: cell_size,
+			entries: vec![],
+			cells: BTreeMap::new(),
+		}
+	}
+
+	fn grid_pos(&self, pos: Point2<f32>) -> (i32, i32)
+	{
+		(
+			(pos.x / self.cell_size).floor() as i32,
+			(pos.y / self.cell_size).floor() as i32,
+		)
+	}
+
+	pub fn insert(&mut self, id: T, pos: Point2<f32>, size: f32, kind: comps::CollideKind)
+	{
+		let idx = self.entries.len();
+		let entry = Entry {
+			id: id,
+			pos: pos,
+			size: size,
+			kind: kind,
+		};
+		let (x1, y1) = self.grid_pos(entry.min());
+		let (x2, y2) = self.grid_pos(entry.max());
+		for y in y1..=y2
+		{
+			for x in x1..=x2
+			{
+				self.cells.entry((x, y)).or_default().push(idx);
+			}
+		}
+		self.entries.push(entry);
+	}
+
+	/// Returns every overlapping pair of solids whose kinds collide, each pair exactly once. The
+	/// order is deterministic.
+	pub fn pairs(&self) -> Vec<(T, T)>
+	{
+		let mut pairs = vec![];
+		for (&cell, idxs) in &self.cells
+		{
+			for (i, &idx1) in idxs.iter().enumerate()
+			{
+				for &idx2 in &idxs[i + 1..]
+				{
+					let e1 = &self.entries[idx1];
+					let e2 = &self.entries[idx2];
+					if !e1.kind.collides_with(&e2.kind)
+					{
+						continue;
+					}
+					if (e1.pos - e2.pos).norm() >= e1.size + e2.size
+					{
+						continue;
+					}
+					// Pairs sharing several cells are only reported by the cell holding the
+					// corner of their bounding box intersection.
+					let (min1, min2) = (e1.min(), e2.min());
+					let corner = Point2::new(min1.x.max(min2.x), min1.y.max(min2.y));
+					if self.grid_pos(corner) == cell
+					{
+						pairs.push((e1.id, e2.id));
+					}
+				}
+			}
+		}
+		pairs
+	}
+}
+
+#[test]
+fn test_broad_phase()
+{
+	use rand::prelude::*;
+
+	let mut rng = StdRng::seed_from_u64(0);
+	let mut solids = vec![];
+	for i in 0..300
+	{
+		let kind = if i % 10 == 0
+		{
+			comps::CollideKind::Ship
+		}
+		else
+		{
+			comps::CollideKind::Car
+		};
+		solids.push((
+			Point2::new(rng.gen_range(-50.0..690.0), rng.gen_range(-50.0..530.0)),
+			rng.gen_range(4.0..80.0),
+			kind,
+		));
+	}
+
+	let mut broad_phase = BroadPhase::new(32.);
+	for (i, &(pos, size, kind)) in solids.iter().enumerate()
+	{
+		broad_phase.insert(i, pos, size, kind);
+	}
+	let mut pairs: Vec<_> = broad_phase
+		.pairs()
+		.into_iter()
+		.map(|(a, b)| (a.min(b), a.max(b)))
+		.collect();
+	pairs.sort();
+
+	let mut expected = vec![];
+	for (i, (pos1, size1, kind1)) in solids.iter().enumerate()
+	{
+		for (j, (pos2, size2, kind2)) in solids.iter().enumerate().skip(i + 1)
+		{
+			if kind1.collides_with(kind2) && (pos1 - pos2).norm() < size1 + size2
+			{
+				expected.push((i, j));
+			}
+		}
+	}
+	assert!(!expected.is_empty());
+	assert_eq!(pairs, expected);
+}
@@ -1,17 +1,16 @@
-use crate::sprite;
-use allegro::*;
 use na::{Point2, Vector2};
 use nalgebra as na;
 use rand::prelude::*;
+use serde_derive::{Deserialize, Serialize};
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct Position
 {
 	pub pos: Point2<f32>,
 	pub dir: f32,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct Velocity
 {
 	pub pos: Vector2<f32>,
@@ -24,29 +23,87 @@ pub struct Connection
 	pub child: Option<hecs::Entity>,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct Ship;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CargoKind
+{
+	#[default]
+	Medicine,
+	Food,
+	Samples,
+	Vaccine,
+}
+
+impl CargoKind
+{
+	pub const ALL: [CargoKind; 4] = [
+		CargoKind::Medicine,
+		CargoKind::Food,
+		CargoKind::Samples,
+		CargoKind::Vaccine,
+	];
+
+	pub fn name(self) -> &'static str
+	{
+		match self
+		{
+			CargoKind::Medicine => "Medicine",
+			CargoKind::Food => "Food",
+			CargoKind::Samples => "Samples",
+			CargoKind::Vaccine => "Vaccine",
+		}
+	}
+
+	pub fn sprite(self) -> String
+	{
+		let idx = match self
+		{
+			CargoKind::Medicine => 2,
+			CargoKind::Food => 4,
+			CargoKind::Samples => 3,
+			CargoKind::Vaccine => 5,
+		};
+		format!("data/car{}.cfg", idx)
+	}
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct Car
 {
 	pub attached: bool,
+	#[serde(default)]
+	pub cargo: CargoKind,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Doodad
 {
 	pub sprite: String,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct AffectedByGravity;
 
-#[derive(Copy, Clone, Debug)]
+/// An infected ship that hunts the player's train.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct Derelict
+{
+	/// Where it is flying to next.
+	pub waypoint: Point2<f32>,
+}
+
+/// Drifting debris that damages whatever it hits.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct Hazard;
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum CollideKind
 {
 	Ship,
 	Car,
+	Hazard,
 }
 
 impl CollideKind
@@ -59,18 +116,26 @@ impl CollideKind
 			(CollideKind::Ship, CollideKind::Car) => true,
 			(CollideKind::Car, CollideKind::Ship) => true,
 			(CollideKind::Car, CollideKind::Car) => false,
+			(CollideKind::Hazard, CollideKind::Hazard) => false,
+			(CollideKind::Hazard, _) | (_, CollideKind::Hazard) => true,
 		}
 	}
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct Mass
+{
+	pub mass: f32,
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct Solid
 {
 	pub size: f32,
 	pub kind: CollideKind,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct CarCorpse
 {
 	pub multiplier: f32,
@@ -78,34 +143,66 @@ pub struct CarCorpse
 	pub explode: bool,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct TimeToDie
 {
 	pub time_to_die: f64,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum DrawKind
 {
 	Ship,
 	Car,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct Drawable
 {
 	pub kind: DrawKind,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Sprite
 {
 	pub sprite: String,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Engine
 {
 	pub on: bool,
 	pub sprite: String,
 }
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct Hull
+{
+	pub health: f32,
+	pub max_health: f32,
+}
+
+impl Hull
+{
+	pub fn new(max_health: f32) -> Self
+	{
+		Self {
+			health: max_health,
+			max_health: max_health,
+		}
+	}
+
+	pub fn fraction(&self) -> f32
+	{
+		self.health / self.max_health
+	}
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct Fuel
+{
+	pub fuel: f32,
+	pub max_fuel: f32,
+	/// Fuel used per second of thrust.
+	pub use_rate: f32,
+}
@@ -10,6 +10,8 @@ pub enum Action
 	Right,
 	Thrust,
 	ShowMap,
+	Shop,
+	Trajectory,
 }
 
 impl Action
@@ -22,6 +24,8 @@ impl Action
 			Action::Right => "Right",
 			Action::Thrust => "Thrust",
 			Action::ShowMap => "Show Map",
+			Action::Shop => "Shop",
+			Action::Trajectory => "Trajectory",
 		}
 	}
 }
@@ -487,6 +491,14 @@ impl Controls
 			Action::ShowMap,
 			[Some(Input::Keyboard(allegro::KeyCode::Tab)), None],
 		);
+		action_to_inputs.insert(
+			Action::Shop,
+			[Some(Input::Keyboard(allegro::KeyCode::Enter)), None],
+		);
+		action_to_inputs.insert(
+			Action::Trajectory,
+			[Some(Input::Keyboard(allegro::KeyCode::T)), None],
+		);
 
 		Self {
 			action_to_inputs: action_to_inputs,
@@ -494,6 +506,15 @@ impl Controls
 		}
 	}
 
+	/// Gives the actions missing from older options files their default bindings.
+	pub fn add_missing_actions(&mut self)
+	{
+		for (action, inputs) in Self::new().action_to_inputs
+		{
+			self.action_to_inputs.entry(action).or_insert(inputs);
+		}
+	}
+
 	pub fn get_action_string(&self, action: Action) -> String
 	{
 		let mut inputs = vec![];
@@ -514,6 +535,7 @@ pub struct ControlsHandler
 	controls: Controls,
 	input_to_action: BTreeMap<Input, Action>,
 	input_state: HashMap<Input, InputState>,
+	forced_action_states: Option<BTreeMap<Action, f32>>,
 }
 
 impl ControlsHandler
@@ -524,6 +546,7 @@ impl ControlsHandler
 			controls: controls,
 			input_to_action: BTreeMap::new(),
 			input_state: HashMap::new(),
+			forced_action_states: None,
 		};
 		ret.update_derived();
 		ret
@@ -673,8 +696,19 @@ impl ControlsHandler
 		vec![]
 	}
 
+	/// Overrides the live input with the given action states (e.g. when playing back a replay).
+	/// Passing `None` returns to live input.
+	pub fn force_action_states(&mut self, states: Option<BTreeMap<Action, f32>>)
+	{
+		self.forced_action_states = states;
+	}
+
 	pub fn get_action_state(&mut self, action: Action) -> f32
 	{
+		if let Some(forced_action_states) = &self.forced_action_states
+		{
+			return forced_action_states.get(&action).copied().unwrap_or(0.);
+		}
 		let mut ret = 0.;
 		if let Some(inputs) = self.controls.action_to_inputs.get(&action)
 		{
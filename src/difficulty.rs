@@ -0,0 +1,173 @@
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DifficultyLevel
+{
+	Easy,
+	#[default]
+	Normal,
+	Hard,
+	Custom,
+}
+
+impl DifficultyLevel
+{
+	pub const ALL: [DifficultyLevel; 4] = [
+		DifficultyLevel::Easy,
+		DifficultyLevel::Normal,
+		DifficultyLevel::Hard,
+		DifficultyLevel::Custom,
+	];
+
+	pub fn name(self) -> &'static str
+	{
+		match self
+		{
+			DifficultyLevel::Easy => "Easy",
+			DifficultyLevel::Normal => "Normal",
+			DifficultyLevel::Hard => "Hard",
+			DifficultyLevel::Custom => "Custom",
+		}
+	}
+}
+
+/// The rule parameters of a run.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Difficulty
+{
+	pub level: DifficultyLevel,
+	/// Fastest safe landing speed.
+	pub max_vel: f32,
+	pub crash_penalty: i32,
+	/// Chance each day that the disease strikes a planet.
+	pub disease_chance: f32,
+	/// Scales the disease strength set by the story.
+	pub strength_scale: f32,
+	/// Daily research is the larger of this times the number of populated planets and the total
+	/// population divided by `research_pop_divisor`.
+	pub research_per_planet: i32,
+	pub research_pop_divisor: i32,
+	/// Research needed for the cure. Story research thresholds are scaled relative to 1000.
+	pub cure_research: i32,
+}
+
+impl Difficulty
+{
+	/// The built-in parameters for a level. `Custom` starts out as `Normal`.
+	pub fn preset(level: DifficultyLevel) -> Self
+	{
+		let normal = Self {
+			level: level,
+			max_vel: 25.,
+			crash_penalty: 1000,
+			disease_chance: 0.5,
+			strength_scale: 1.,
+			research_per_planet: 1,
+			research_pop_divisor: 5,
+			cure_research: 1000,
+		};
+		match level
+		{
+			DifficultyLevel::Easy => Self {
+				max_vel: 30.,
+				crash_penalty: 500,
+				disease_chance: 0.35,
+				strength_scale: 0.5,
+				research_pop_divisor: 4,
+				cure_research: 800,
+				..normal
+			},
+			DifficultyLevel::Normal | DifficultyLevel::Custom => normal,
+			DifficultyLevel::Hard => Self {
+				max_vel: 20.,
+				crash_penalty: 1500,
+				disease_chance: 0.65,
+				strength_scale: 1.5,
+				research_pop_divisor: 6,
+				cure_research: 1200,
+				..normal
+			},
+		}
+	}
+
+	/// Clamps the parameters to values the simulation can handle, since custom ones come from
+	/// user-editable files.
+	pub fn clamped(self) -> Self
+	{
+		let default = Self::preset(self.level);
+		let finite = |v: f32, d: f32| if v.is_finite() { v } else { d };
+		Self {
+			level: self.level,
+			max_vel: finite(self.max_vel, default.max_vel).max(1.),
+			crash_penalty: self.crash_penalty.max(0),
+			disease_chance: finite(self.disease_chance, default.disease_chance).clamp(0., 1.),
+			strength_scale: finite(self.strength_scale, default.strength_scale).max(0.),
+			research_per_planet: self.research_per_planet.max(0),
+			research_pop_divisor: self.research_pop_divisor.max(1),
+			cure_research: self.cure_research.max(1),
+		}
+	}
+
+	/// A short hash of the parameters, to tell custom rule sets apart.
+	pub fn fingerprint(&self) -> u32
+	{
+		let mut bytes = vec![];
+		bytes.extend_from_slice(&self.max_vel.to_le_bytes());
+		bytes.extend_from_slice(&self.crash_penalty.to_le_bytes());
+		bytes.extend_from_slice(&self.disease_chance.to_le_bytes());
+		bytes.extend_from_slice(&self.strength_scale.to_le_bytes());
+		bytes.extend_from_slice(&self.research_per_planet.to_le_bytes());
+		bytes.extend_from_slice(&self.research_pop_divisor.to_le_bytes());
+		bytes.extend_from_slice(&self.cure_research.to_le_bytes());
+		// FNV-1a, so the value is stable between builds.
+		bytes
+			.iter()
+			.fold(0x811c9dc5, |h, &b| (h ^ b as u32).wrapping_mul(0x01000193))
+	}
+
+	pub fn strength(&self, strength: i32) -> i32
+	{
+		(strength as f32 * self.strength_scale).round() as i32
+	}
+
+	/// Converts a research threshold out of 1000 to this difficulty.
+	pub fn research_threshold(&self, research: i32) -> i32
+	{
+		research * self.cure_research / 1000
+	}
+}
+
+impl Default for Difficulty
+{
+	fn default() -> Self
+	{
+		Self::preset(DifficultyLevel::Normal)
+	}
+}
+
+#[test]
+fn test_clamped()
+{
+	let difficulty = Difficulty {
+		max_vel: f32::NAN,
+		crash_penalty: -5,
+		disease_chance: 1.5,
+		research_pop_divisor: 0,
+		cure_research: 0,
+		..Difficulty::preset(DifficultyLevel::Custom)
+	}
+	.clamped();
+	assert_eq!(difficulty.max_vel, 25.);
+	assert_eq!(difficulty.crash_penalty, 0);
+	assert_eq!(difficulty.disease_chance, 1.);
+	assert_eq!(difficulty.research_pop_divisor, 1);
+	assert_eq!(difficulty.cure_research, 1);
+	assert_eq!(
+		Difficulty::preset(DifficultyLevel::Hard).clamped(),
+		Difficulty::preset(DifficultyLevel::Hard)
+	);
+	assert_ne!(
+		difficulty.fingerprint(),
+		Difficulty::preset(DifficultyLevel::Custom).fingerprint()
+	);
+}
@@ -1,5 +1,5 @@
 use crate::error::Result;
-use crate::{atlas, controls, sfx, sprite, utils};
+use crate::{atlas, controls, difficulty, parts, replay, sfx, sim, sprite, utils};
 use allegro::*;
 use allegro_font::*;
 use allegro_image::*;
@@ -28,6 +28,14 @@ pub struct Options
 	pub frac_scale: bool,
 	pub player_ship: i32,
 	pub player_engine: i32,
+	#[serde(default)]
+	pub difficulty: difficulty::DifficultyLevel,
+	/// Rule parameters used by the custom difficulty.
+	#[serde(default)]
+	pub custom_difficulty: difficulty::Difficulty,
+	/// Draw the predicted trajectory of the ship.
+	#[serde(default)]
+	pub show_trajectory: bool,
 
 	pub controls: controls::Controls,
 }
@@ -50,6 +58,25 @@ impl Default for Options
 			controls: controls::Controls::new(),
 			player_ship: 0,
 			player_engine: 0,
+			difficulty: difficulty::DifficultyLevel::Normal,
+			custom_difficulty: difficulty::Difficulty::default(),
+			show_trajectory: false,
+		}
+	}
+}
+
+impl Options
+{
+	pub fn difficulty(&self) -> difficulty::Difficulty
+	{
+		match self.difficulty
+		{
+			difficulty::DifficultyLevel::Custom => difficulty::Difficulty {
+				level: difficulty::DifficultyLevel::Custom,
+				..self.custom_difficulty
+			}
+			.clamped(),
+			level => difficulty::Difficulty::preset(level),
 		}
 	}
 }
@@ -58,6 +85,7 @@ impl Default for Options
 pub enum NextScreen
 {
 	Game,
+	Continue,
 	Menu,
 	InGameMenu,
 	Quit,
@@ -72,6 +100,11 @@ pub struct GameState
 	pub ttf: TtfAddon,
 	pub tick: i64,
 	pub paused: bool,
+	/// Seed for the next sector, random if not set.
+	pub seed: Option<u64>,
+	pub sector_layout: sim::SectorLayout,
+	/// Replay to play back in the next game.
+	pub replay: Option<replay::Replay>,
 
 	pub sfx: sfx::Sfx,
 	pub atlas: atlas::Atlas,
@@ -90,7 +123,8 @@ pub struct GameState
 	pub buffer2: Option<Bitmap>,
 }
 
-pub fn load_options(core: &Core) -> Result<Options>
+/// Returns the path to a file in the user settings directory, creating the directory if needed.
+pub fn user_file_path(core: &Core, file: &str) -> Result<path::PathBuf>
 {
 	let mut path_buf = path::PathBuf::new();
 	if cfg!(feature = "use_user_settings")
@@ -100,10 +134,29 @@ pub fn load_options(core: &Core) -> Result<Options>
 				.map_err(|_| "Couldn't get standard path".to_string())?,
 		);
 	}
-	path_buf.push("options.cfg");
+	std::fs::create_dir_all(&path_buf).map_err(|_| "Couldn't create directory".to_string())?;
+	path_buf.push(file);
+	Ok(path_buf)
+}
+
+pub fn save_exists(core: &Core) -> bool
+{
+	user_file_path(core, "save.cfg")
+		.map(|p| p.exists())
+		.unwrap_or(false)
+}
+
+pub fn load_options(core: &Core) -> Result<Options>
+{
+	let path_buf = user_file_path(core, "options.cfg")?;
 	if path_buf.exists()
 	{
-		utils::load_config(path_buf.to_str().unwrap())
+		let mut options: Options = utils::load_config(path_buf.to_str().unwrap())?;
+		options.controls.add_missing_actions();
+		options.custom_difficulty = options.custom_difficulty.clamped();
+		options.player_ship = utils::clamp(options.player_ship, 0, parts::NUM_PARTS - 1);
+		options.player_engine = utils::clamp(options.player_engine, 0, parts::NUM_PARTS - 1);
+		Ok(options)
 	}
 	else
 	{
@@ -113,16 +166,7 @@ pub fn load_options(core: &Core) -> Result<Options>
 
 pub fn save_options(core: &Core, options: &Options) -> Result<()>
 {
-	let mut path_buf = path::PathBuf::new();
-	if cfg!(feature = "use_user_settings")
-	{
-		path_buf.push(
-			core.get_standard_path(StandardPath::UserSettings)
-				.map_err(|_| "Couldn't get standard path".to_string())?,
-		);
-	}
-	std::fs::create_dir_all(&path_buf).map_err(|_| "Couldn't create directory".to_string())?;
-	path_buf.push("options.cfg");
+	let path_buf = user_file_path(core, "options.cfg")?;
 	utils::save_config(path_buf.to_str().unwrap(), &options)
 }
 
@@ -159,6 +203,9 @@ impl GameState
 			ttf: ttf,
 			sfx: sfx,
 			paused: false,
+			seed: None,
+			sector_layout: sim::SectorLayout::default(),
+			replay: None,
 			atlas: atlas::Atlas::new(1024),
 			ui_font: None,
 			draw_scale: 1.,
@@ -279,14 +326,4 @@ impl GameState
 	{
 		self.tick as f64 * utils::DT as f64
 	}
-
-	pub fn player_ship(&self) -> String
-	{
-		format!("data/ship{}.cfg", self.options.player_ship + 1)
-	}
-
-	pub fn player_engine(&self) -> String
-	{
-		format!("data/engine{}.cfg", self.options.player_engine + 1)
-	}
 }
@@ -0,0 +1,297 @@
+use crate::components as comps;
+use crate::error::{Error, Result};
+use crate::{difficulty, sim};
+use nalgebra::Point2;
+
+const MAGIC: &[u8; 4] = b"WGST";
+const VERSION: u8 = 2;
+
+/// A ship or car of the ghost train.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GhostObject
+{
+	pub sprite: u16,
+	pub pos: Point2<f32>,
+	pub dir: f32,
+}
+
+/// Where the ghost train was at a single tick.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GhostFrame
+{
+	pub day: i32,
+	pub cell_pos: Point2<usize>,
+	pub objects: Vec<GhostObject>,
+}
+
+/// Per-tick positions of the player's train over a whole run. `frames[i]` is the state after
+/// tick `i`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ghost
+{
+	pub seed: u64,
+	pub sprites: Vec<String>,
+	pub frames: Vec<GhostFrame>,
+}
+
+impl Ghost
+{
+	pub fn new(seed: u64) -> Self
+	{
+		Self {
+			seed: seed,
+			sprites: vec![],
+			frames: vec![],
+		}
+	}
+
+	pub fn file_name(
+		seed: u64, layout: &sim::SectorLayout, difficulty: &difficulty::Difficulty,
+	) -> String
+	{
+		let mut name = format!("ghost_{}", seed);
+		if *layout != sim::SectorLayout::default()
+		{
+			name += &format!(
+				"_{}x{}_{}",
+				layout.width,
+				layout.height,
+				layout.topology.to_str()
+			);
+		}
+		match difficulty.level
+		{
+			difficulty::DifficultyLevel::Normal => (),
+			difficulty::DifficultyLevel::Custom =>
+			{
+				name += &format!("_custom_{:08x}", difficulty.fingerprint());
+			}
+			level => name += &format!("_{}", level.name().to_lowercase()),
+		}
+		name + ".ghost"
+	}
+
+	/// Records the player's ship and the cars attached to it.
+	pub fn record(&mut self, map: &sim::Map)
+	{
+		let mut objects = vec![];
+		let mut cur = Some(map.player);
+		while let Some(e) = cur
+		{
+			cur = None;
+			if let (Ok(position), Ok(sprite)) = (
+				map.world.get::<&comps::Position>(e),
+				map.world.get::<&comps::Sprite>(e),
+			)
+			{
+				objects.push(GhostObject {
+					sprite: self.sprite_idx(&sprite.sprite),
+					pos: position.pos,
+					dir: position.dir,
+				});
+			}
+			if let Ok(connection) = map.world.get::<&comps::Connection>(e)
+			{
+				cur = connection.child;
+			}
+		}
+		self.frames.push(GhostFrame {
+			day: map.day,
+			cell_pos: map.cell_pos,
+			objects: objects,
+		});
+	}
+
+	fn sprite_idx(&mut self, sprite: &str) -> u16
+	{
+		if let Some(idx) = self.sprites.iter().position(|s| s == sprite)
+		{
+			return idx as u16;
+		}
+		self.sprites.push(sprite.to_string());
+		(self.sprites.len() - 1) as u16
+	}
+
+	/// Returns the frame for the given tick if the ghost was in the given cell on the given day.
+	pub fn frame(&self, tick: i64, day: i32, cell_pos: Point2<usize>) -> Option<&GhostFrame>
+	{
+		self.frames
+			.get(tick as usize)
+			.filter(|f| f.day == day && f.cell_pos == cell_pos)
+	}
+
+	pub fn to_bytes(&self) -> Result<Vec<u8>>
+	{
+		let too_many = |what| -> Result<Vec<u8>> { Err(format!("Too many ghost {}", what).into()) };
+		if self.sprites.len() > u16::MAX as usize
+		{
+			return too_many("sprites");
+		}
+		let mut bytes = vec![];
+		bytes.extend_from_slice(MAGIC);
+		bytes.push(VERSION);
+		bytes.extend_from_slice(&self.seed.to_le_bytes());
+		bytes.extend_from_slice(&(self.sprites.len() as u16).to_le_bytes());
+		for sprite in &self.sprites
+		{
+			bytes.extend_from_slice(&(sprite.len() as u16).to_le_bytes());
+			bytes.extend_from_slice(sprite.as_bytes());
+		}
+		bytes.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+		for frame in &self.frames
+		{
+			bytes.extend_from_slice(&frame.day.to_le_bytes());
+			bytes.extend_from_slice(&(frame.cell_pos.x as u16).to_le_bytes());
+			bytes.extend_from_slice(&(frame.cell_pos.y as u16).to_le_bytes());
+			if frame.objects.len() > u16::MAX as usize
+			{
+				return too_many("objects");
+			}
+			bytes.extend_from_slice(&(frame.objects.len() as u16).to_le_bytes());
+			for object in &frame.objects
+			{
+				bytes.extend_from_slice(&object.sprite.to_le_bytes());
+				bytes.extend_from_slice(&object.pos.x.to_le_bytes());
+				bytes.extend_from_slice(&object.pos.y.to_le_bytes());
+				bytes.extend_from_slice(&object.dir.to_le_bytes());
+			}
+		}
+		Ok(bytes)
+	}
+
+	pub fn from_bytes(bytes: &[u8]) -> Result<Self>
+	{
+		let mut reader = Reader {
+			bytes: bytes,
+			offset: 0,
+		};
+		if reader.take(MAGIC.len())? != MAGIC
+		{
+			return Err("Not a ghost file".to_string().into());
+		}
+		let version = reader.take(1)?[0];
+		if version != VERSION
+		{
+			return Err(format!("Unsupported ghost version: {}", version).into());
+		}
+		let mut ghost = Ghost::new(u64::from_le_bytes(reader.take(8)?.try_into().unwrap()));
+		let num_sprites = reader.u16()?;
+		for _ in 0..num_sprites
+		{
+			let len = reader.u16()? as usize;
+			let sprite = String::from_utf8(reader.take(len)?.to_vec())
+				.map_err(|e| Error::new("Invalid sprite name".into(), Some(Box::new(e))))?;
+			ghost.sprites.push(sprite);
+		}
+		let num_frames = u32::from_le_bytes(reader.take(4)?.try_into().unwrap());
+		for _ in 0..num_frames
+		{
+			let day = i32::from_le_bytes(reader.take(4)?.try_into().unwrap());
+			let x = reader.u16()? as usize;
+			let y = reader.u16()? as usize;
+			let num_objects = reader.u16()?;
+			let mut objects = vec![];
+			for _ in 0..num_objects
+			{
+				let sprite = reader.u16()?;
+				if sprite as usize >= ghost.sprites.len()
+				{
+					return Err(format!("Invalid sprite index: {}", sprite).into());
+				}
+				objects.push(GhostObject {
+					sprite: sprite,
+					pos: Point2::new(reader.f32()?, reader.f32()?),
+					dir: reader.f32()?,
+				});
+			}
+			ghost.frames.push(GhostFrame {
+				day: day,
+				cell_pos: Point2::new(x, y),
+				objects: objects,
+			});
+		}
+		Ok(ghost)
+	}
+
+	pub fn save(&self, file: &str) -> Result<()>
+	{
+		std::fs::write(file, self.to_bytes()?)
+			.map_err(|e| Error::new(format!("Couldn't write '{}'", file), Some(Box::new(e))))
+	}
+
+	pub fn load(file: &str) -> Result<Self>
+	{
+		let bytes = std::fs::read(file)
+			.map_err(|e| Error::new(format!("Couldn't read '{}'", file), Some(Box::new(e))))?;
+		Self::from_bytes(&bytes).map_err(|e| e.context(format!("Couldn't load '{}'", file)))
+	}
+}
+
+struct Reader<'l>
+{
+	bytes: &'l [u8],
+	offset: usize,
+}
+
+impl<'l> Reader<'l>
+{
+	fn take(&mut self, len: usize) -> Result<&'l [u8]>
+	{
+		if self.offset + len > self.bytes.len()
+		{
+			return Err("Truncated ghost file".to_string().into());
+		}
+		let res = &self.bytes[self.offset..self.offset + len];
+		self.offset += len;
+		Ok(res)
+	}
+
+	fn u16(&mut self) -> Result<u16>
+	{
+		Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+	}
+
+	fn f32(&mut self) -> Result<f32>
+	{
+		Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+	}
+}
+
+#[test]
+fn test_ghost_round_trip()
+{
+	let mut map = sim::Map::new(
+		4321,
+		sim::SectorLayout::default(),
+		difficulty::Difficulty::default(),
+		crate::parts::HullDef::default(),
+		crate::parts::EngineDef::default(),
+	)
+	.unwrap();
+	let mut ghost = Ghost::new(map.seed);
+	ghost.record(&map);
+	let input = sim::Input {
+		left: false,
+		right: true,
+		thrust: true,
+	};
+	for _ in 0..100
+	{
+		map.logic(&input).unwrap();
+		ghost.record(&map);
+	}
+	assert_eq!(ghost.frames.len(), 101);
+	assert_eq!(ghost.sprites, vec!["data/ship1.cfg".to_string()]);
+	assert!(ghost.frame(100, map.day, map.cell_pos).is_some());
+	assert!(ghost.frame(100, map.day + 1, map.cell_pos).is_none());
+
+	let bytes = ghost.to_bytes().unwrap();
+	assert_eq!(Ghost::from_bytes(&bytes).unwrap(), ghost);
+	assert!(Ghost::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+	// A long train must not wrap the object count.
+	let object = ghost.frames[0].objects[0].clone();
+	ghost.frames[0].objects = vec![object; 300];
+	let bytes = ghost.to_bytes().unwrap();
+	assert_eq!(Ghost::from_bytes(&bytes).unwrap(), ghost);
+}
@@ -0,0 +1,162 @@
+use crate::{controls, sim};
+use rand::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+
+/// The fading messages drawn over the game.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Hud
+{
+	pub message: String,
+	pub message_time: f64,
+	pub score_message: String,
+	pub last_score_change: i32,
+	pub score_time: f64,
+	pub pop_message: String,
+	pub pop_time: f64,
+}
+
+impl Hud
+{
+	pub fn new() -> Self
+	{
+		Self::default()
+	}
+
+	pub fn on_event(
+		&mut self, event: &sim::GameEvent, map: &sim::Map, controls: &controls::Controls,
+	)
+	{
+		let time = map.time();
+		match event
+		{
+			sim::GameEvent::PlayerRespawned { score } =>
+			{
+				self.score_message = format!("{}", score);
+				self.last_score_change = *score;
+				self.score_time = time;
+			}
+			sim::GameEvent::SupplyScored {
+				multiplier, score, ..
+			} =>
+			{
+				self.score_message = format!("+{}x{}", 100., multiplier);
+				self.last_score_change = *score;
+				self.score_time = time;
+			}
+			sim::GameEvent::PopulationIncreased { amount, .. } =>
+			{
+				self.pop_message = format!("+{}", amount);
+				self.pop_time = time;
+			}
+			sim::GameEvent::InfectionReduced { amount, .. } =>
+			{
+				self.pop_message = format!("{} cured", amount);
+				self.pop_time = time;
+			}
+			sim::GameEvent::PopulationImmunised { amount, .. } =>
+			{
+				self.pop_message = format!("{} immune", amount);
+				self.pop_time = time;
+			}
+			sim::GameEvent::SamplesDelivered { research } =>
+			{
+				self.pop_message = format!("+{} research", research);
+				self.pop_time = time;
+			}
+			sim::GameEvent::ContractPosted(contract) =>
+			{
+				self.message = format!(
+					"{} requests\n{} {}\nwithin {} days.",
+					map.cells[contract.cell].name,
+					contract.amount,
+					contract.cargo.name().to_lowercase(),
+					contract.deadline - map.day
+				);
+				self.message_time = time;
+			}
+			sim::GameEvent::ContractCompleted { contract, score } =>
+			{
+				self.message = format!(
+					"{} thanks you\nfor the {}.",
+					map.cells[contract.cell].name,
+					contract.cargo.name().to_lowercase()
+				);
+				self.message_time = time;
+				self.score_message = format!("+{}", score);
+				self.last_score_change = *score;
+				self.score_time = time;
+			}
+			sim::GameEvent::ContractFailed(contract) =>
+			{
+				self.message = format!(
+					"{} waited in vain\nfor the {}.",
+					map.cells[contract.cell].name,
+					contract.cargo.name().to_lowercase()
+				);
+				self.message_time = time;
+			}
+			sim::GameEvent::StoryMessage(message) =>
+			{
+				self.message = message
+					.replace(
+						"{thrust}",
+						&controls.get_action_string(controls::Action::Thrust),
+					)
+					.replace(
+						"{left}",
+						&controls.get_action_string(controls::Action::Left),
+					)
+					.replace(
+						"{right}",
+						&controls.get_action_string(controls::Action::Right),
+					)
+					.replace(
+						"{show_map}",
+						&controls.get_action_string(controls::Action::ShowMap),
+					)
+					.replace("{sector}", &map.name);
+				self.message_time = time;
+			}
+			sim::GameEvent::PlanetInfected { cell, wiped_out } =>
+			{
+				let name = &map.cells[*cell].name;
+				let messages = if *wiped_out
+				{
+					vec![
+						(format!("{name} has been\nwiped out."), 4),
+						(format!("There is no more\nillness at the {name}."), 4),
+						(format!("{name} no longer\nrequires supplies."), 3),
+						(format!("It is too late\nfor people of the {name}."), 3),
+						(format!("{name} has gone silent."), 1),
+					]
+				}
+				else
+				{
+					vec![
+						(format!("Hospitals are\noverwhelmed at the {name}."), 4),
+						(format!("Illness takes for\nthe worse at the {name}."), 4),
+						(format!("Disease spreads\nat the {name}."), 3),
+						(format!("{name} is hit by\nthe infection."), 3),
+						(format!("The living envy\nthe dead at the {name}."), 3),
+						(format!("The end is near\nat the {name}."), 1),
+					]
+				};
+				self.message = messages
+					.choose_weighted(&mut thread_rng(), |m_w| m_w.1)
+					.unwrap()
+					.0
+					.clone();
+				self.message_time = time;
+			}
+			sim::GameEvent::Defeat =>
+			{
+				self.message = format!(
+					"{} has no more people\nleft to save.\nYour services are no longer necessary.",
+					map.name
+				);
+				self.message_time = time;
+			}
+			_ => (),
+		}
+	}
+}
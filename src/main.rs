@@ -5,14 +5,24 @@
 
 mod astar;
 mod atlas;
+mod broad_phase;
 mod components;
 mod controls;
+mod difficulty;
 mod error;
 mod game;
 mod game_state;
+mod ghost;
+mod hud;
 mod menu;
+mod nav;
+mod parts;
+mod replay;
 mod sfx;
+mod sim;
 mod sprite;
+mod stats;
+mod story;
 mod ui;
 mod utils;
 
@@ -30,9 +40,61 @@ enum Screen
 	Menu(menu::Menu),
 }
 
+fn parse_args(state: &mut game_state::GameState) -> Result<()>
+{
+	let mut args = std::env::args().skip(1);
+	while let Some(arg) = args.next()
+	{
+		match arg.as_str()
+		{
+			"--seed" =>
+			{
+				let seed = args.next().ok_or("--seed requires a value".to_string())?;
+				state.seed = Some(
+					seed.parse()
+						.map_err(|_| format!("Invalid seed: {}", seed))?,
+				);
+			}
+			"--sector-size" =>
+			{
+				let size = args
+					.next()
+					.ok_or("--sector-size requires a value".to_string())?;
+				let dims: Vec<usize> = size
+					.split('x')
+					.map(|d| d.parse())
+					.collect::<std::result::Result<_, _>>()
+					.map_err(|_| format!("Invalid sector size: {}", size))?;
+				if dims.len() != 2 || !dims.iter().all(|&d| sim::SectorLayout::valid_size(d))
+				{
+					return Err(format!("Invalid sector size: {}", size).into());
+				}
+				state.sector_layout.width = dims[0];
+				state.sector_layout.height = dims[1];
+			}
+			"--topology" =>
+			{
+				let topology = args
+					.next()
+					.ok_or("--topology requires a value".to_string())?;
+				state.sector_layout.topology = sim::Topology::from_str(&topology)
+					.ok_or(format!("Invalid topology: {}", topology))?;
+			}
+			"--replay" =>
+			{
+				let file = args.next().ok_or("--replay requires a file".to_string())?;
+				state.replay = Some(replay::Replay::load(&file)?);
+			}
+			_ => return Err(format!("Unknown argument: {}", arg).into()),
+		}
+	}
+	Ok(())
+}
+
 fn real_main() -> Result<()>
 {
 	let mut state = game_state::GameState::new()?;
+	parse_args(&mut state)?;
 	state.sfx.cache_sample("data/ui1.ogg")?;
 	state.sfx.cache_sample("data/ui2.ogg")?;
 	state.sfx.cache_sample("data/explosion.ogg")?;
@@ -89,7 +151,14 @@ fn real_main() -> Result<()>
 	let mut quit = false;
 	let mut draw = true;
 
-	let mut cur_screen = Screen::Menu(menu::Menu::new(&mut state)?);
+	let mut cur_screen = if state.replay.is_some()
+	{
+		Screen::Game(game::Game::new(&mut state)?)
+	}
+	else
+	{
+		Screen::Menu(menu::Menu::new(&mut state)?)
+	};
 	//let mut cur_screen = Screen::Game(game::Game::new(&mut state)?);
 
 	let mut logics_without_draw = 0;
@@ -284,6 +353,10 @@ fn real_main() -> Result<()>
 				{
 					cur_screen = Screen::Game(game::Game::new(&mut state)?);
 				}
+				game_state::NextScreen::Continue =>
+				{
+					cur_screen = Screen::Game(game::Game::resume(&mut state)?);
+				}
 				game_state::NextScreen::Menu =>
 				{
 					cur_screen = Screen::Menu(menu::Menu::new(&mut state)?);
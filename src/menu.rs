@@ -67,6 +67,7 @@ impl Menu
 			match action
 			{
 				ui::Action::Start => return Ok(Some(game_state::NextScreen::Game)),
+				ui::Action::Continue => return Ok(Some(game_state::NextScreen::Continue)),
 				ui::Action::Quit => return Ok(Some(game_state::NextScreen::Quit)),
 				_ => (),
 			}
@@ -0,0 +1,135 @@
+use crate::{astar, utils};
+use na::Point2;
+use nalgebra as na;
+
+/// Size of a navigation grid cell, in world units.
+pub const NAV_CELL_SIZE: f32 = 16.;
+
+/// Grid of the open space of a map cell, used to find paths around the ground.
+pub struct NavGrid
+{
+	size: usize,
+	solid: Vec<bool>,
+	astar: astar::AStarContext,
+}
+
+impl NavGrid
+{
+	/// Rasterizes the ground of a `width` by `height` map cell. Nodes closer than `margin` to the
+	/// ground are solid too.
+	pub fn new(ground: &[(f32, f32)], width: f32, height: f32, margin: f32) -> Self
+	{
+		let num_x = (width / NAV_CELL_SIZE).ceil() as usize;
+		let num_y = (height / NAV_CELL_SIZE).ceil() as usize;
+		// The path-finder wants a square grid, the excess is left solid.
+		let size = num_x.max(num_y);
+		let mut solid = vec![true; size * size];
+		for y in 0..num_y
+		{
+			for x in 0..num_x
+			{
+				let pos = node_pos(Point2::new(x as i32, y as i32));
+				solid[y * size + x] = is_inside(ground, pos) || is_near(ground, pos, margin);
+			}
+		}
+		Self {
+			size: size,
+			solid: solid,
+			astar: astar::AStarContext::new(size),
+		}
+	}
+
+	fn node(&self, pos: Point2<f32>) -> Point2<i32>
+	{
+		let max = self.size as i32 - 1;
+		Point2::new(
+			((pos.x / NAV_CELL_SIZE) as i32).clamp(0, max),
+			((pos.y / NAV_CELL_SIZE) as i32).clamp(0, max),
+		)
+	}
+
+	pub fn is_solid(&self, pos: Point2<f32>) -> bool
+	{
+		let node = self.node(pos);
+		self.solid[node.y as usize * self.size + node.x as usize]
+	}
+
+	/// Returns the centers of the nodes from `from` to `to`, or to the node closest to `to` if it
+	/// can't be reached.
+	pub fn path(&mut self, from: Point2<f32>, to: Point2<f32>) -> Vec<Point2<f32>>
+	{
+		let from = self.node(from);
+		let to = self.node(to);
+		let solid = &self.solid;
+		let size = self.size;
+		let mut path = self.astar.solve(
+			from,
+			to,
+			|p| solid[p.y as usize * size + p.x as usize],
+			|_| 0.,
+		);
+		path.reverse();
+		path.into_iter().map(node_pos).collect()
+	}
+}
+
+fn node_pos(node: Point2<i32>) -> Point2<f32>
+{
+	Point2::new(
+		(node.x as f32 + 0.5) * NAV_CELL_SIZE,
+		(node.y as f32 + 0.5) * NAV_CELL_SIZE,
+	)
+}
+
+/// Even-odd test against the ground, closed into a polygon.
+pub fn is_inside(ground: &[(f32, f32)], pos: Point2<f32>) -> bool
+{
+	let mut inside = false;
+	for (i, &(x1, y1)) in ground.iter().enumerate()
+	{
+		let (x2, y2) = ground[(i + 1) % ground.len()];
+		if (y1 > pos.y) != (y2 > pos.y) && pos.x < x1 + (pos.y - y1) / (y2 - y1) * (x2 - x1)
+		{
+			inside = !inside;
+		}
+	}
+	inside
+}
+
+fn is_near(ground: &[(f32, f32)], pos: Point2<f32>, margin: f32) -> bool
+{
+	ground.windows(2).any(|w| {
+		// Cheap rejection of far away segments.
+		if pos.x < w[0].0.min(w[1].0) - margin
+			|| pos.x > w[0].0.max(w[1].0) + margin
+			|| pos.y < w[0].1.min(w[1].1) - margin
+			|| pos.y > w[0].1.max(w[1].1) + margin
+		{
+			return false;
+		}
+		let nearest = utils::nearest_line_point(
+			Point2::new(w[0].0, w[0].1),
+			Point2::new(w[1].0, w[1].1),
+			pos,
+		);
+		(nearest - pos).norm() < margin
+	})
+}
+
+#[test]
+fn test_nav_grid()
+{
+	// A wall in the middle, with a gap at the top.
+	let ground = [(300., 100.), (340., 100.), (340., 480.), (300., 480.)];
+	let mut nav = NavGrid::new(&ground, 640., 480., 8.);
+	assert!(nav.is_solid(Point2::new(320., 240.)));
+	assert!(!nav.is_solid(Point2::new(320., 50.)));
+
+	let from = Point2::new(100., 400.);
+	let to = Point2::new(540., 400.);
+	let path = nav.path(from, to);
+	assert!((path[0] - from).norm() < NAV_CELL_SIZE);
+	assert!((path[path.len() - 1] - to).norm() < NAV_CELL_SIZE);
+	assert!(path.iter().all(|&p| !nav.is_solid(p)));
+	assert!(path.iter().any(|p| p.y < 100.));
+}
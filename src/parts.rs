@@ -0,0 +1,100 @@
+use crate::error::Result;
+use crate::utils;
+use serde_derive::{Deserialize, Serialize};
+
+/// A hull the player can pick, loaded from `data/shipN_def.cfg`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HullDef
+{
+	pub name: String,
+	pub sprite: String,
+	/// Collision radius.
+	pub size: f32,
+	pub mass: f32,
+	/// Rotation rate, in radians per second.
+	pub turn_rate: f32,
+}
+
+impl Default for HullDef
+{
+	fn default() -> Self
+	{
+		Self {
+			name: "Courier".to_string(),
+			sprite: "data/ship1.cfg".to_string(),
+			size: 16.,
+			mass: 1.,
+			turn_rate: 2.,
+		}
+	}
+}
+
+impl HullDef
+{
+	pub fn load(file: &str) -> Result<Self>
+	{
+		utils::load_config(file).map_err(|e| e.context(format!("Couldn't load '{}'", file)))
+	}
+}
+
+/// An engine the player can pick, loaded from `data/engineN_def.cfg`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EngineDef
+{
+	pub name: String,
+	pub sprite: String,
+	pub thrust: f32,
+	/// Fuel use relative to an average engine.
+	pub fuel_use: f32,
+	pub sound: String,
+}
+
+impl Default for EngineDef
+{
+	fn default() -> Self
+	{
+		Self {
+			name: "Standard".to_string(),
+			sprite: "data/engine1.cfg".to_string(),
+			thrust: 96.,
+			fuel_use: 1.,
+			sound: "data/engine1.ogg".to_string(),
+		}
+	}
+}
+
+impl EngineDef
+{
+	pub fn load(file: &str) -> Result<Self>
+	{
+		utils::load_config(file).map_err(|e| e.context(format!("Couldn't load '{}'", file)))
+	}
+}
+
+/// Number of hulls and of engines to pick from.
+pub const NUM_PARTS: i32 = 5;
+
+pub fn hull_file(idx: i32) -> String
+{
+	format!("data/ship{}_def.cfg", idx + 1)
+}
+
+pub fn engine_file(idx: i32) -> String
+{
+	format!("data/engine{}_def.cfg", idx + 1)
+}
+
+#[test]
+fn test_part_files()
+{
+	for i in 0..NUM_PARTS
+	{
+		HullDef::load(&hull_file(i)).unwrap();
+		EngineDef::load(&engine_file(i)).unwrap();
+	}
+	assert_eq!(HullDef::load(&hull_file(0)).unwrap(), HullDef::default());
+	assert_eq!(
+		EngineDef::load(&engine_file(0)).unwrap(),
+		EngineDef::default()
+	);
+}
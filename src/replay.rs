@@ -0,0 +1,335 @@
+use crate::error::{Error, Result};
+use crate::{controls, difficulty, sim};
+use std::collections::BTreeMap;
+
+const MAGIC: &[u8; 4] = b"WRPL";
+const VERSION: u8 = 1;
+
+/// The action states for a single tick.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Frame
+{
+	pub left: bool,
+	pub right: bool,
+	pub thrust: bool,
+	pub show_map: bool,
+}
+
+impl Frame
+{
+	fn to_bits(self) -> u8
+	{
+		self.left as u8
+			| (self.right as u8) << 1
+			| (self.thrust as u8) << 2
+			| (self.show_map as u8) << 3
+	}
+
+	fn from_bits(bits: u8) -> Self
+	{
+		Self {
+			left: bits & 1 != 0,
+			right: bits & 2 != 0,
+			thrust: bits & 4 != 0,
+			show_map: bits & 8 != 0,
+		}
+	}
+
+	pub fn action_states(&self) -> BTreeMap<controls::Action, f32>
+	{
+		let mut states = BTreeMap::new();
+		states.insert(controls::Action::Left, self.left as i32 as f32);
+		states.insert(controls::Action::Right, self.right as i32 as f32);
+		states.insert(controls::Action::Thrust, self.thrust as i32 as f32);
+		states.insert(controls::Action::ShowMap, self.show_map as i32 as f32);
+		states
+	}
+}
+
+/// Everything needed to reproduce a run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Replay
+{
+	pub seed: u64,
+	pub layout: sim::SectorLayout,
+	pub difficulty: difficulty::Difficulty,
+	pub player_ship: i32,
+	pub player_engine: i32,
+	/// Upgrades bought in the shop, with the index of the frame they were bought before.
+	pub purchases: Vec<(usize, sim::Upgrade)>,
+	pub frames: Vec<Frame>,
+}
+
+impl Replay
+{
+	pub fn new(
+		seed: u64, layout: sim::SectorLayout, difficulty: difficulty::Difficulty, player_ship: i32,
+		player_engine: i32,
+	) -> Self
+	{
+		Self {
+			seed: seed,
+			layout: layout,
+			difficulty: difficulty,
+			player_ship: player_ship,
+			player_engine: player_engine,
+			purchases: vec![],
+			frames: vec![],
+		}
+	}
+
+	/// Header and purchases, followed by run-length encoded frames stored as (count: u16, bits:
+	/// u8).
+	pub fn to_bytes(&self) -> Vec<u8>
+	{
+		let mut bytes = vec![];
+		bytes.extend_from_slice(MAGIC);
+		bytes.push(VERSION);
+		bytes.extend_from_slice(&self.seed.to_le_bytes());
+		bytes.extend_from_slice(&self.player_ship.to_le_bytes());
+		bytes.extend_from_slice(&self.player_engine.to_le_bytes());
+		bytes.extend_from_slice(&(self.layout.width as u16).to_le_bytes());
+		bytes.extend_from_slice(&(self.layout.height as u16).to_le_bytes());
+		bytes.push(match self.layout.topology
+		{
+			sim::Topology::Torus => 0,
+			sim::Topology::Cylinder => 1,
+			sim::Topology::Bounded => 2,
+			sim::Topology::Wormholes => 3,
+		});
+		let difficulty = &self.difficulty;
+		bytes.push(match difficulty.level
+		{
+			difficulty::DifficultyLevel::Easy => 0,
+			difficulty::DifficultyLevel::Normal => 1,
+			difficulty::DifficultyLevel::Hard => 2,
+			difficulty::DifficultyLevel::Custom => 3,
+		});
+		bytes.extend_from_slice(&difficulty.max_vel.to_le_bytes());
+		bytes.extend_from_slice(&difficulty.crash_penalty.to_le_bytes());
+		bytes.extend_from_slice(&difficulty.disease_chance.to_le_bytes());
+		bytes.extend_from_slice(&difficulty.strength_scale.to_le_bytes());
+		bytes.extend_from_slice(&difficulty.research_per_planet.to_le_bytes());
+		bytes.extend_from_slice(&difficulty.research_pop_divisor.to_le_bytes());
+		bytes.extend_from_slice(&difficulty.cure_research.to_le_bytes());
+		bytes.extend_from_slice(&(self.purchases.len() as u16).to_le_bytes());
+		for &(frame, upgrade) in &self.purchases
+		{
+			bytes.extend_from_slice(&(frame as u64).to_le_bytes());
+			bytes.push(upgrade as u8);
+		}
+
+		let mut frames = self.frames.iter().map(|f| f.to_bits()).peekable();
+		while let Some(bits) = frames.next()
+		{
+			let mut count = 1u16;
+			while count < u16::MAX && frames.peek() == Some(&bits)
+			{
+				frames.next();
+				count += 1;
+			}
+			bytes.extend_from_slice(&count.to_le_bytes());
+			bytes.push(bits);
+		}
+		bytes
+	}
+
+	pub fn from_bytes(bytes: &[u8]) -> Result<Self>
+	{
+		// Magic, version, seed, ship, engine, layout, difficulty and the purchase count.
+		let header_len = MAGIC.len() + 1 + 8 + 4 + 4 + 5 + 29 + 2;
+		if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC
+		{
+			return Err("Not a replay file".to_string().into());
+		}
+		if bytes.len() < header_len
+		{
+			return Err("Truncated replay file".to_string().into());
+		}
+		let version = bytes[MAGIC.len()];
+		if version != VERSION
+		{
+			return Err(format!("Unsupported replay version: {}", version).into());
+		}
+		let mut offset = MAGIC.len() + 1;
+		let seed = u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+		offset += 8;
+		let player_ship = i32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+		offset += 4;
+		let player_engine = i32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+		offset += 4;
+
+		let width = u16::from_le_bytes([bytes[offset], bytes[offset + 1]]) as usize;
+		let height = u16::from_le_bytes([bytes[offset + 2], bytes[offset + 3]]) as usize;
+		if !sim::SectorLayout::valid_size(width) || !sim::SectorLayout::valid_size(height)
+		{
+			return Err(format!("Invalid sector size: {}x{}", width, height).into());
+		}
+		let layout = sim::SectorLayout {
+			width: width,
+			height: height,
+			topology: match bytes[offset + 4]
+			{
+				0 => sim::Topology::Torus,
+				1 => sim::Topology::Cylinder,
+				2 => sim::Topology::Bounded,
+				3 => sim::Topology::Wormholes,
+				t => return Err(format!("Invalid topology: {}", t).into()),
+			},
+		};
+		offset += 5;
+
+		let mut difficulty = difficulty::Difficulty::default();
+		difficulty.level = match bytes[offset]
+		{
+			0 => difficulty::DifficultyLevel::Easy,
+			1 => difficulty::DifficultyLevel::Normal,
+			2 => difficulty::DifficultyLevel::Hard,
+			3 => difficulty::DifficultyLevel::Custom,
+			l => return Err(format!("Invalid difficulty: {}", l).into()),
+		};
+		let field = |i: usize| -> [u8; 4] {
+			bytes[offset + 1 + 4 * i..offset + 5 + 4 * i]
+				.try_into()
+				.unwrap()
+		};
+		difficulty.max_vel = f32::from_le_bytes(field(0));
+		difficulty.crash_penalty = i32::from_le_bytes(field(1));
+		difficulty.disease_chance = f32::from_le_bytes(field(2));
+		difficulty.strength_scale = f32::from_le_bytes(field(3));
+		difficulty.research_per_planet = i32::from_le_bytes(field(4));
+		difficulty.research_pop_divisor = i32::from_le_bytes(field(5));
+		difficulty.cure_research = i32::from_le_bytes(field(6));
+		let difficulty = difficulty.clamped();
+		offset += 29;
+
+		let mut replay = Replay::new(seed, layout, difficulty, player_ship, player_engine);
+		let num_purchases = u16::from_le_bytes([bytes[offset], bytes[offset + 1]]) as usize;
+		offset += 2;
+		if bytes.len() < offset + 9 * num_purchases
+		{
+			return Err("Truncated replay file".to_string().into());
+		}
+		for _ in 0..num_purchases
+		{
+			let frame = u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+			let upgrade = *sim::Upgrade::ALL
+				.get(bytes[offset + 8] as usize)
+				.ok_or_else(|| format!("Invalid upgrade: {}", bytes[offset + 8]))?;
+			replay.purchases.push((frame as usize, upgrade));
+			offset += 9;
+		}
+
+		let runs = &bytes[offset..];
+		if !runs.len().is_multiple_of(3)
+		{
+			return Err("Truncated replay file".to_string().into());
+		}
+		for run in runs.chunks(3)
+		{
+			let count = u16::from_le_bytes([run[0], run[1]]);
+			let frame = Frame::from_bits(run[2]);
+			replay
+				.frames
+				.extend(std::iter::repeat_n(frame, count as usize));
+		}
+		Ok(replay)
+	}
+
+	pub fn save(&self, file: &str) -> Result<()>
+	{
+		std::fs::write(file, self.to_bytes())
+			.map_err(|e| Error::new(format!("Couldn't write '{}'", file), Some(Box::new(e))))
+	}
+
+	pub fn load(file: &str) -> Result<Self>
+	{
+		let bytes = std::fs::read(file)
+			.map_err(|e| Error::new(format!("Couldn't read '{}'", file), Some(Box::new(e))))?;
+		Self::from_bytes(&bytes).map_err(|e| e.context(format!("Couldn't load '{}'", file)))
+	}
+}
+
+/// Feeds the frames of a replay back one tick at a time.
+pub struct Playback
+{
+	replay: Replay,
+	cur_frame: usize,
+}
+
+impl Playback
+{
+	pub fn new(replay: Replay) -> Self
+	{
+		Self {
+			replay: replay,
+			cur_frame: 0,
+		}
+	}
+
+	pub fn replay(&self) -> &Replay
+	{
+		&self.replay
+	}
+
+	/// Upgrades bought before the next frame.
+	pub fn purchases(&self) -> Vec<sim::Upgrade>
+	{
+		self.replay
+			.purchases
+			.iter()
+			.filter(|&&(frame, _)| frame == self.cur_frame)
+			.map(|&(_, upgrade)| upgrade)
+			.collect()
+	}
+
+	pub fn next_frame(&mut self) -> Option<Frame>
+	{
+		let frame = self.replay.frames.get(self.cur_frame).copied();
+		self.cur_frame += 1;
+		frame
+	}
+}
+
+#[test]
+fn test_replay_round_trip()
+{
+	let layout = sim::SectorLayout {
+		width: 5,
+		height: 9,
+		topology: sim::Topology::Cylinder,
+	};
+	let difficulty = difficulty::Difficulty {
+		crash_penalty: 750,
+		..difficulty::Difficulty::preset(difficulty::DifficultyLevel::Custom)
+	};
+	let mut replay = Replay::new(1234, layout, difficulty, 2, 3);
+	replay.purchases = vec![(120, sim::Upgrade::Hull), (3000, sim::Upgrade::Thrust)];
+	for i in 0..100000
+	{
+		replay.frames.push(Frame {
+			left: (i / 50) % 3 == 0,
+			right: false,
+			thrust: i < 70000,
+			show_map: i % 1000 < 10,
+		});
+	}
+	let bytes = replay.to_bytes();
+	assert!(bytes.len() < replay.frames.len() / 2);
+	assert_eq!(Replay::from_bytes(&bytes).unwrap(), replay);
+	assert!(Replay::from_bytes(&bytes[..10]).is_err());
+	let mut bad_bytes = bytes.clone();
+	// Zero the width, which follows the header.
+	let width_offset = MAGIC.len() + 1 + 8 + 4 + 4;
+	bad_bytes[width_offset] = 0;
+	bad_bytes[width_offset + 1] = 0;
+	assert!(Replay::from_bytes(&bad_bytes).is_err());
+
+	let mut playback = Playback::new(replay);
+	let mut bought = vec![];
+	while playback.next_frame().is_some()
+	{
+		bought.extend(playback.purchases());
+	}
+	assert_eq!(bought, vec![sim::Upgrade::Hull, sim::Upgrade::Thrust]);
+}
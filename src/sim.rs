@@ -0,0 +1,3913 @@
+use crate::difficulty::Difficulty;
+use crate::error::Result;
+use crate::{astar, broad_phase, components as comps, nav, parts, story, utils};
+use na::{Point2, Rotation2, Vector2};
+use nalgebra as na;
+use rand::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const WIDTH: f32 = 640.;
+pub const HEIGHT: f32 = 480.;
+const COLLISION_CELL_SIZE: f32 = 32.;
+const TRAIN_LINK_LENGTH: f32 = 24.;
+const TRAIN_STIFFNESS: f32 = 60.;
+const TRAIN_DAMPING: f32 = 4.;
+/// Daily chance of each infected person infecting each susceptible one, per point of strength.
+const INFECTION_RATE: f32 = 0.15;
+/// How much the infected in neighbouring cells count towards the infection pressure of a cell.
+const SPREAD_RATE: f32 = 0.25;
+const MORTALITY: f32 = 0.2;
+const RECOVERY_RATE: f32 = 0.3;
+const IMMUNITY_LOSS: f32 = 0.05;
+/// Research gained for each delivery of research samples that were asked for.
+const SAMPLE_RESEARCH: i32 = 10;
+const MAX_CONTRACTS: usize = 3;
+/// Daily chance of a new contract being posted.
+const CONTRACT_CHANCE: f64 = 0.5;
+/// Score and research awarded per crate of a completed contract.
+const CONTRACT_SCORE: i32 = 250;
+const CONTRACT_RESEARCH: i32 = 5;
+const MAX_FUEL: f32 = 100.;
+/// Fuel used per second of thrust by an engine of average efficiency.
+const FUEL_USE: f32 = 10.;
+/// Fuel gained per second while landed somewhere that refuels.
+const REFUEL_RATE: f32 = 50.;
+const SHIP_HULL: f32 = 100.;
+const CAR_HULL: f32 = 50.;
+/// Damage of an impact at the fastest safe landing speed, before the angle is accounted for.
+const IMPACT_DAMAGE: f32 = 60.;
+/// Damage per second of a car dragged along the ground.
+const SCRAPE_DAMAGE: f32 = 30.;
+/// Multiplier of the score of deliveries made after landing on the landing pad.
+const LANDING_PAD_BONUS: f32 = 1.5;
+/// Health regained per second while landed on a populated planet.
+const REPAIR_RATE: f32 = 25.;
+const TRAIN_CAPACITY: i32 = 6;
+const HAZARDS_PER_STRENGTH: i32 = 3;
+const MAX_HAZARDS: i32 = 12;
+/// Damage from a hazard hit at low speed. Faster hits do more.
+const HAZARD_DAMAGE: f32 = 35.;
+const DERELICT_CHANCE: f64 = 0.3;
+const MAX_DERELICTS: i32 = 3;
+const DERELICT_HULL: f32 = 30.;
+/// Damage from a derelict ramming into the train at low speed.
+const DERELICT_DAMAGE: f32 = 40.;
+const DERELICT_THRUST: f32 = 64.;
+const DERELICT_ROTATION_RATE: f32 = 2.5;
+/// Speed derelicts try to fly at.
+const DERELICT_SPEED: f32 = 40.;
+/// Ticks between path-finding updates of the derelicts.
+const DERELICT_REPATH_TICKS: i64 = 20;
+/// Clearance the derelicts keep from the ground.
+const NAV_MARGIN: f32 = 20.;
+const MAX_UPGRADE_LEVEL: i32 = 3;
+/// Cost of the first level of an upgrade. Each level after that costs as much again.
+const UPGRADE_COST: i32 = 1000;
+/// How far ahead the trajectory of the ship is predicted, in seconds.
+const TRAJECTORY_TIME: f32 = 3.;
+/// Height above the ground at which the atmosphere of a planet thins out to nothing.
+pub const ATMOSPHERE_HEIGHT: f32 = 64.;
+const ATMOSPHERE_CHANCE: f64 = 0.4;
+const MOON_CHANCE: f64 = 0.3;
+const GRAVITY_WELL_CHANCE: f64 = 0.2;
+const WIND_CHANCE: f64 = 0.2;
+
+pub fn spawn_ship(
+	hull: &parts::HullDef, engine: &parts::EngineDef, pos: Point2<f32>, dir: f32,
+	world: &mut hecs::World,
+) -> Result<hecs::Entity>
+{
+	let entity = world.spawn((
+		comps::Position { pos: pos, dir: dir },
+		comps::Velocity {
+			pos: Vector2::new(0., 0.),
+			dir: 0.,
+		},
+		comps::Ship,
+		comps::AffectedByGravity,
+		comps::Solid {
+			kind: comps::CollideKind::Ship,
+			size: hull.size,
+		},
+		comps::Mass { mass: hull.mass },
+		comps::Sprite {
+			sprite: hull.sprite.clone(),
+		},
+		comps::Engine {
+			sprite: engine.sprite.clone(),
+			on: false,
+		},
+		comps::Fuel {
+			fuel: MAX_FUEL,
+			max_fuel: MAX_FUEL,
+			use_rate: FUEL_USE * engine.fuel_use,
+		},
+		comps::Hull::new(SHIP_HULL),
+		comps::Connection { child: None },
+	));
+	Ok(entity)
+}
+
+pub fn spawn_car(
+	pos: Point2<f32>, rng: &mut impl Rng, world: &mut hecs::World,
+) -> Result<hecs::Entity>
+{
+	let cargo = *comps::CargoKind::ALL.choose(rng).unwrap();
+	let entity = world.spawn((
+		comps::Position {
+			pos: pos,
+			dir: rng.gen_range(0.0..2.0 * utils::PI),
+		},
+		comps::Velocity {
+			pos: Vector2::new(0., 0.),
+			dir: *[-1., 1.].choose(rng).unwrap(),
+		},
+		comps::Car {
+			attached: false,
+			cargo: cargo,
+		},
+		comps::Solid {
+			kind: comps::CollideKind::Car,
+			size: 8.,
+		},
+		comps::Mass { mass: 0.5 },
+		comps::Hull::new(CAR_HULL),
+		comps::Sprite {
+			sprite: cargo.sprite(),
+		},
+		comps::Connection { child: None },
+	));
+	Ok(entity)
+}
+
+pub fn spawn_derelict(pos: Point2<f32>, world: &mut hecs::World) -> Result<hecs::Entity>
+{
+	let entity = world.spawn((
+		comps::Position {
+			pos: pos,
+			dir: -utils::PI / 2.,
+		},
+		comps::Velocity {
+			pos: Vector2::new(0., 0.),
+			dir: 0.,
+		},
+		comps::Derelict { waypoint: pos },
+		comps::AffectedByGravity,
+		comps::Solid {
+			kind: comps::CollideKind::Ship,
+			size: 16.,
+		},
+		comps::Mass { mass: 1. },
+		comps::Sprite {
+			sprite: "data/ship5.cfg".to_string(),
+		},
+		comps::Engine {
+			sprite: "data/engine3.cfg".to_string(),
+			on: false,
+		},
+		comps::Hull::new(DERELICT_HULL),
+	));
+	Ok(entity)
+}
+
+pub fn spawn_hazard(
+	pos: Point2<f32>, rng: &mut impl Rng, world: &mut hecs::World,
+) -> Result<hecs::Entity>
+{
+	let theta = rng.gen_range(0.0..2.0 * utils::PI);
+	let speed = rng.gen_range(20.0..60.0);
+	let entity = world.spawn((
+		comps::Position {
+			pos: pos,
+			dir: rng.gen_range(0.0..2.0 * utils::PI),
+		},
+		comps::Velocity {
+			pos: speed * Vector2::new(theta.cos(), theta.sin()),
+			dir: rng.gen_range(-1.0..1.0),
+		},
+		comps::Hazard,
+		comps::Solid {
+			kind: comps::CollideKind::Hazard,
+			size: 12.,
+		},
+		comps::Mass { mass: 4. },
+		comps::Sprite {
+			sprite: "data/asteroid1.cfg".to_string(),
+		},
+	));
+	Ok(entity)
+}
+
+pub fn spawn_star(pos: Point2<f32>, seed: usize, world: &mut hecs::World) -> Result<hecs::Entity>
+{
+	let sprite = format!("data/star{}.cfg", 1 + seed % 5);
+	let entity = world.spawn((
+		comps::Position { pos: pos, dir: 0. },
+		comps::Doodad { sprite: sprite },
+	));
+	Ok(entity)
+}
+
+pub fn spawn_deliver(pos: Point2<f32>, time: f64, world: &mut hecs::World) -> Result<hecs::Entity>
+{
+	let sprite = "data/deliver.cfg".to_string();
+	let entity = world.spawn((
+		comps::Position { pos: pos, dir: 0. },
+		comps::Doodad { sprite: sprite },
+		comps::TimeToDie {
+			time_to_die: time + 0.5,
+		},
+	));
+	Ok(entity)
+}
+
+pub fn spawn_explosion(pos: Point2<f32>, time: f64, world: &mut hecs::World)
+	-> Result<hecs::Entity>
+{
+	let sprite = "data/explosion.cfg".to_string();
+	let entity = world.spawn((
+		comps::Position { pos: pos, dir: 0. },
+		comps::Doodad { sprite: sprite },
+		comps::TimeToDie {
+			time_to_die: time + 0.5,
+		},
+	));
+	Ok(entity)
+}
+
+pub fn spawn_building(
+	position: comps::Position, seed: usize, world: &mut hecs::World,
+) -> Result<hecs::Entity>
+{
+	let sprite = format!("data/building{}.cfg", 1 + seed % 2);
+	let entity = world.spawn((position, comps::Doodad { sprite: sprite }));
+	Ok(entity)
+}
+
+pub fn spawn_car_corpse(
+	position: comps::Position, sprite: comps::Sprite, explode: bool, time_to_die: f64,
+	multiplier: f32, rng: &mut impl Rng, world: &mut hecs::World,
+) -> Result<hecs::Entity>
+{
+	let speed_mult = if explode { 1. } else { 0. };
+	let entity = world.spawn((
+		position,
+		sprite,
+		comps::Velocity {
+			pos: Vector2::new(rng.gen_range(-32.0..32.0), rng.gen_range(-32.0..32.0)) * speed_mult,
+			dir: rng.gen_range(-2.0..2.0) * speed_mult,
+		},
+		comps::CarCorpse {
+			multiplier: multiplier,
+			time_to_die: time_to_die,
+			explode: explode,
+		},
+	));
+	Ok(entity)
+}
+
+/// The kind of planet in a cell, which shapes its terrain. The strength is what the cell's
+/// `GravityField` was generated from; only the field moves things around.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum Gravity
+{
+	None,
+	Down(f32),
+	Center(f32),
+}
+
+/// A point mass, like a planet or a moon.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Attractor
+{
+	pub pos: Point2<f32>,
+	pub strength: f32,
+	/// Radius of the body. Beyond it the pull falls off with the square of the distance, and
+	/// inside it fades to nothing at the centre. Attractors without one pull equally hard
+	/// everywhere.
+	pub falloff: Option<f32>,
+}
+
+impl Attractor
+{
+	fn pull(&self, pos: Point2<f32>) -> Vector2<f32>
+	{
+		let dv = self.pos - pos;
+		let dist = dv.norm();
+		if dist == 0.
+		{
+			return Vector2::new(0., 0.);
+		}
+		let f = match self.falloff
+		{
+			Some(falloff) if dist < falloff => dist / falloff,
+			Some(falloff) => (falloff / dist).powi(2),
+			None => 1.,
+		};
+		f * self.strength * dv / dist
+	}
+}
+
+/// A rectangle of space with a steady wind blowing through it.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Wind
+{
+	pub min: Point2<f32>,
+	pub max: Point2<f32>,
+	pub accel: Vector2<f32>,
+}
+
+/// Everything that pushes objects around in a cell.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct GravityField
+{
+	/// Pull that is the same everywhere, like above a flat planet.
+	pub uniform: Vector2<f32>,
+	pub attractors: Vec<Attractor>,
+	pub winds: Vec<Wind>,
+	/// Drag per second at the ground, thinning out up to `ATMOSPHERE_HEIGHT` above it.
+	pub drag: f32,
+}
+
+impl GravityField
+{
+	/// The field of a lone planet.
+	pub fn new(gravity: Gravity, center: Point2<f32>) -> Self
+	{
+		let mut field = Self::default();
+		match gravity
+		{
+			Gravity::None => (),
+			Gravity::Down(v) => field.uniform = Vector2::new(0., v),
+			Gravity::Center(v) => field.attractors.push(Attractor {
+				pos: center,
+				strength: v,
+				falloff: None,
+			}),
+		}
+		field
+	}
+
+	/// Gravitational acceleration at `pos`.
+	pub fn pull(&self, pos: Point2<f32>) -> Vector2<f32>
+	{
+		self.attractors
+			.iter()
+			.fold(self.uniform, |pull, a| pull + a.pull(pos))
+	}
+
+	pub fn wind(&self, pos: Point2<f32>) -> Vector2<f32>
+	{
+		self.winds
+			.iter()
+			.filter(|w| pos.x >= w.min.x && pos.y >= w.min.y && pos.x < w.max.x && pos.y < w.max.y)
+			.fold(Vector2::new(0., 0.), |wind, w| wind + w.accel)
+	}
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MapCell
+{
+	pub name: String,
+	pub ground: Vec<(f32, f32)>,
+	/// Kind of planet. The physics only look at `field`.
+	pub gravity: Gravity,
+	pub population: i32,
+	/// The part of the population that is sick.
+	#[serde(default)]
+	pub infected: i32,
+	/// The part of the population that is immune.
+	#[serde(default)]
+	pub recovered: i32,
+	/// The cargo this planet asks for.
+	#[serde(default)]
+	pub demand: Vec<comps::CargoKind>,
+	/// Range of `ground` points of the flat landing pad of a planet. Landing on it scores more,
+	/// and refuels the ship on unpopulated planets.
+	#[serde(default)]
+	pub landing_pad: Option<(usize, usize)>,
+	/// Filled in from `gravity` when loading saves from before gravity fields.
+	#[serde(default)]
+	pub field: GravityField,
+	pub center: Point2<f32>,
+	pub stars: Vec<Point2<f32>>,
+	pub buildings: Vec<comps::Position>,
+}
+
+impl MapCell
+{
+	pub fn susceptible(&self) -> i32
+	{
+		self.population - self.infected - self.recovered
+	}
+
+	/// Whether `pos` on the ground is on the landing pad.
+	pub fn on_landing_pad(&self, pos: Point2<f32>) -> bool
+	{
+		if let Some((start, end)) = self.landing_pad
+		{
+			let w = WIDTH / 96.;
+			self.ground[start..end]
+				.iter()
+				.any(|&(x, y)| (Point2::new(x, y) - pos).norm() < 2. * w)
+		}
+		else
+		{
+			false
+		}
+	}
+
+	/// Whether the ship can refuel when landed at `pos`.
+	pub fn refuels_at(&self, pos: Point2<f32>) -> bool
+	{
+		self.population > 0 || self.on_landing_pad(pos)
+	}
+
+	/// Removes people, the healthy ones first.
+	fn lose_population(&mut self, amount: i32)
+	{
+		self.population = utils::max(0, self.population - amount);
+		let excess = utils::max(0, self.infected + self.recovered - self.population);
+		let recovered_lost = utils::min(excess, self.recovered);
+		self.recovered -= recovered_lost;
+		self.infected -= excess - recovered_lost;
+	}
+
+	fn new(names: &mut Vec<String>, rng: &mut impl Rng) -> Self
+	{
+		let num_points = 96;
+		let mut ground = Vec::with_capacity(num_points + 2);
+		let mut buildings = Vec::with_capacity(9);
+		let population;
+		let name;
+		let mut landing_pad = None;
+
+		let num_stars = rng.gen_range(10..20);
+		let mut stars = Vec::with_capacity(num_stars);
+		for _ in 0..num_stars
+		{
+			stars.push(Point2::new(
+				rng.gen_range(0.0..WIDTH),
+				rng.gen_range(0.0..HEIGHT),
+			));
+		}
+
+		let center = Point2::new(
+			WIDTH / 2. + rng.gen_range(-16.0..16.0),
+			HEIGHT / 2. + rng.gen_range(-16.0..16.0),
+		);
+
+		let strength = rng.gen_range(16.0..32.0);
+		let choices = [
+			(Gravity::Center(strength), 4),
+			(Gravity::Down(strength), 1),
+			(Gravity::None, 10),
+		];
+		let (gravity, _) = choices.choose_weighted(rng, |g_w| g_w.1).unwrap();
+
+		match gravity
+		{
+			Gravity::Down(_) =>
+			{
+				let w = WIDTH / (num_points - 1) as f32;
+
+				let mut y1 = 0.;
+				let mut segment_lengths = vec![];
+				let mut cur_points = 0;
+				loop
+				{
+					let segment = rng.gen_range(6..12);
+					segment_lengths.push(segment);
+					if segment + cur_points > num_points
+					{
+						break;
+					}
+					cur_points += segment;
+				}
+				let num_segments = segment_lengths.len();
+				segment_lengths[num_segments - 1] = num_points - cur_points;
+				let landing_segment = rng.gen_range(1..num_segments - 1);
+
+				for (s, &segment) in segment_lengths.iter().enumerate()
+				{
+					let a = 600.;
+					let b = -a;
+					let c = 50.;
+					let x = s as f32 / (num_segments - 1) as f32;
+
+					let amp = a * x * x + b * x + c;
+
+					let y2 = if s == landing_segment
+					{
+						y1
+					}
+					else
+					{
+						rng.gen_range(-1.0..=1.0) * amp
+					};
+					let a = -rng.gen_range(100.0..300.0);
+
+					if s == landing_segment
+					{
+						landing_pad = Some((ground.len(), ground.len() + segment));
+					}
+					for i in 0..segment
+					{
+						let x = i as f32 / segment as f32;
+						let c = y1;
+						let b = y2 - a - c;
+						let y = if s == landing_segment
+						{
+							y1
+						}
+						else
+						{
+							a * x * x + b * x + c
+						};
+						ground.push((ground.len() as f32 * w, 300. + y));
+					}
+					y1 = y2;
+				}
+				let choices = [0, 1, 2, 3, 4, 5];
+				population = *choices
+					.choose_weighted(rng, |&p| {
+						if p == 0
+						{
+							3
+						}
+						else
+						{
+							1
+						}
+					})
+					.unwrap();
+				ground.push((WIDTH, HEIGHT));
+				ground.push((0., HEIGHT));
+				name = format!("{} System", names.pop().unwrap_or("Maximus".to_string()));
+
+				for i in 0..9
+				{
+					let idx = 5 + i * 9;
+					buildings.push(comps::Position {
+						pos: Point2::new(ground[idx].0, ground[idx].1),
+						dir: -utils::PI / 2.,
+					});
+				}
+			}
+			Gravity::Center(_) =>
+			{
+				let mut r1 = 0.;
+
+				let mut segment_lengths = vec![];
+				let mut cur_points = 0;
+				loop
+				{
+					let segment = rng.gen_range(10..20);
+					segment_lengths.push(segment);
+					if segment + cur_points > num_points
+					{
+						break;
+					}
+					cur_points += segment;
+				}
+				let num_segments = segment_lengths.len();
+				segment_lengths[num_segments - 1] = num_points - cur_points;
+				let landing_segment = rng.gen_range(0..num_segments - 1);
+				for (s, &segment) in segment_lengths.iter().enumerate()
+				{
+					let a = 60.;
+					let b = -a;
+					let c = 0.;
+					let x = s as f32 / (num_segments - 1) as f32;
+
+					let amp = a * x * x + b * x + c;
+					let r2 = if s == landing_segment
+					{
+						r1
+					}
+					else
+					{
+						rng.gen_range(-1.0..=1.0) * amp
+					};
+					let a = rng.gen_range(100.0..150.0);
+
+					if s == landing_segment
+					{
+						landing_pad = Some((ground.len(), ground.len() + segment));
+					}
+					for i in 0..segment
+					{
+						let x = i as f32 / segment as f32;
+						let c = r1;
+						let b = r2 - a - c;
+						let r = if s == landing_segment
+						{
+							100. + r1
+						}
+						else
+						{
+							100. + a * x * x + b * x + c
+						};
+						let theta = 2. * utils::PI * ground.len() as f32 / num_points as f32;
+						ground.push((r * theta.cos() + center.x, r * theta.sin() + center.y));
+					}
+					r1 = r2;
+				}
+				let choices = [0, 1, 2, 3, 4, 5];
+				population = *choices
+					.choose_weighted(rng, |&p| {
+						if p == 0
+						{
+							6
+						}
+						else
+						{
+							1
+						}
+					})
+					.unwrap();
+				name = format!("{} System", names.pop().unwrap_or("Maximus".to_string()));
+				for i in 0..9
+				{
+					let idx = 5 + i * 9;
+					let pos = ground[idx];
+					buildings.push(comps::Position {
+						pos: Point2::new(pos.0, pos.1),
+						dir: (pos.1 - center.y).atan2(pos.0 - center.x),
+					});
+				}
+			}
+			Gravity::None =>
+			{
+				population = 0;
+				name = "Empty Space".to_string();
+			}
+		};
+
+		buildings.shuffle(rng);
+
+		let demand = if population > 0
+		{
+			comps::CargoKind::ALL
+				.choose_multiple(rng, 2)
+				.copied()
+				.collect()
+		}
+		else
+		{
+			vec![]
+		};
+
+		let mut field = GravityField::new(*gravity, center);
+		match *gravity
+		{
+			Gravity::Center(v) =>
+			{
+				// A moon in the sky tugs at everything near it.
+				if rng.gen_bool(MOON_CHANCE)
+				{
+					let theta = rng.gen_range(0.0..2. * utils::PI);
+					let r = rng.gen_range(180.0..220.0);
+					field.attractors.push(Attractor {
+						pos: center + r * Vector2::new(theta.cos(), 0.75 * theta.sin()),
+						strength: v,
+						falloff: Some(24.),
+					});
+				}
+			}
+			Gravity::None =>
+			{
+				if rng.gen_bool(GRAVITY_WELL_CHANCE)
+				{
+					field.attractors.push(Attractor {
+						pos: Point2::new(
+							rng.gen_range(128.0..WIDTH - 128.),
+							rng.gen_range(128.0..HEIGHT - 128.),
+						),
+						strength: rng.gen_range(40.0..80.0),
+						falloff: Some(24.),
+					});
+				}
+			}
+			Gravity::Down(_) => (),
+		}
+		if !matches!(gravity, Gravity::None) && rng.gen_bool(ATMOSPHERE_CHANCE)
+		{
+			field.drag = rng.gen_range(0.5..1.5);
+		}
+		if rng.gen_bool(WIND_CHANCE)
+		{
+			let y = rng.gen_range(0.0..HEIGHT / 2.);
+			let dir = if rng.gen_bool(0.5) { 1. } else { -1. };
+			field.winds.push(Wind {
+				min: Point2::new(0., y),
+				max: Point2::new(WIDTH, y + rng.gen_range(64.0..128.0)),
+				accel: Vector2::new(dir * rng.gen_range(8.0..16.0), 0.),
+			});
+		}
+
+		Self {
+			name: name,
+			population: population,
+			infected: 0,
+			recovered: 0,
+			demand: demand,
+			landing_pad: landing_pad,
+			field: field,
+			center: center,
+			ground: ground,
+			gravity: *gravity,
+			stars: stars,
+			buildings: buildings,
+		}
+	}
+
+	/// Acceleration due to gravity at `pos`.
+	pub fn gravity_at(&self, pos: Point2<f32>) -> Vector2<f32>
+	{
+		self.field.pull(pos)
+	}
+
+	/// Drag per second at `pos`.
+	pub fn drag_at(&self, pos: Point2<f32>) -> f32
+	{
+		if self.field.drag == 0.
+		{
+			return 0.;
+		}
+		let altitude = self
+			.ground
+			.windows(2)
+			.map(|w| {
+				let nearest = utils::nearest_line_point(
+					Point2::new(w[0].0, w[0].1),
+					Point2::new(w[1].0, w[1].1),
+					pos,
+				);
+				(nearest - pos).norm()
+			})
+			.fold(f32::INFINITY, f32::min);
+		self.field.drag * utils::max(0., 1. - altitude / ATMOSPHERE_HEIGHT)
+	}
+
+	/// Acceleration of a free object at `pos` moving at `vel`: gravity, wind and drag.
+	pub fn acceleration(&self, pos: Point2<f32>, vel: Vector2<f32>) -> Vector2<f32>
+	{
+		self.gravity_at(pos) + self.field.wind(pos) - self.drag_at(pos) * vel
+	}
+
+	fn segment(&self, i: usize) -> (Point2<f32>, Point2<f32>)
+	{
+		let (x1, y1) = self.ground[i - 1];
+		let (x2, y2) = self.ground[i];
+		(Point2::new(x1, y1), Point2::new(x2, y2))
+	}
+
+	/// The `collide` result for touching ground segment `i` at `pos`.
+	fn contact(&self, i: usize, pos: Point2<f32>) -> (f32, Vector2<f32>, Point2<f32>)
+	{
+		let (v1, v2) = self.segment(i);
+		let nearest = utils::nearest_line_point(v1, v2, pos);
+		let normal = -Vector2::new(v1.y - v2.y, v2.x - v1.x).normalize();
+		let pull = self.gravity_at(pos);
+		let gravity_normal = if pull.norm() > 0.
+		{
+			-pull.normalize()
+		}
+		else
+		{
+			// Weightless ground still has an up.
+			match self.gravity
+			{
+				Gravity::None => Vector2::new(0., 0.),
+				Gravity::Down(_) => Vector2::new(0., -1.),
+				Gravity::Center(_) => (pos - self.center).normalize(),
+			}
+		};
+		(normal.dot(&gravity_normal), normal, nearest)
+	}
+
+	pub fn collide(&self, pos: Point2<f32>, size: f32) -> Option<(f32, Vector2<f32>, Point2<f32>)>
+	{
+		for i in 1..self.ground.len()
+		{
+			let (v1, v2) = self.segment(i);
+			if (utils::nearest_line_point(v1, v2, pos) - pos).norm() < size
+			{
+				return Some(self.contact(i, pos));
+			}
+		}
+		None
+	}
+
+	/// Like `collide`, but for a solid moving from `start` to `end`. Returns the earliest time of
+	/// impact in [0, 1] along with the `collide` result at that time.
+	pub fn sweep(
+		&self, start: Point2<f32>, end: Point2<f32>, size: f32,
+	) -> Option<(f32, (f32, Vector2<f32>, Point2<f32>))>
+	{
+		let delta = end - start;
+		let mut best: Option<(f32, usize)> = None;
+		for i in 1..self.ground.len()
+		{
+			if let Some(toi) = self.segment_toi(i, start, delta, size)
+			{
+				if best.is_none_or(|(best_toi, _)| toi < best_toi)
+				{
+					best = Some((toi, i));
+				}
+			}
+		}
+		best.map(|(toi, i)| (toi, self.contact(i, start + delta * toi)))
+	}
+
+	/// Earliest time in [0, 1] at which a circle moving by `delta` from `start` touches ground
+	/// segment `i`.
+	fn segment_toi(
+		&self, i: usize, start: Point2<f32>, delta: Vector2<f32>, size: f32,
+	) -> Option<f32>
+	{
+		let (v1, v2) = self.segment(i);
+		if (utils::nearest_line_point(v1, v2, start) - start).norm() < size
+		{
+			return Some(0.);
+		}
+		let mut toi: Option<f32> = None;
+
+		// The face: the centre reaches the line at `size` from the segment on the side it
+		// starts on.
+		let dir = v2 - v1;
+		if dir.norm() > 0.
+		{
+			let normal = Vector2::new(-dir.y, dir.x).normalize();
+			let dist = normal.dot(&(start - v1));
+			let side = dist.signum();
+			let approach = side * normal.dot(&delta);
+			let offset = side * size * normal;
+			if side * dist >= size
+				&& approach < 0.
+				&& utils::intersect_segment_segment(start - offset, start + delta - offset, v1, v2)
+			{
+				toi = Some((side * dist - size) / -approach);
+			}
+		}
+
+		// The end points: solve |start + t * delta - v| = size.
+		let a = delta.norm_squared();
+		if a > 0.
+		{
+			for v in [v1, v2]
+			{
+				let w = start - v;
+				let b = 2. * w.dot(&delta);
+				let c = w.norm_squared() - size * size;
+				let disc = b * b - 4. * a * c;
+				if disc < 0.
+				{
+					continue;
+				}
+				let t = (-b - disc.sqrt()) / (2. * a);
+				if (0.0..=1.).contains(&t) && toi.is_none_or(|toi| t < toi)
+				{
+					toi = Some(t);
+				}
+			}
+		}
+		toi.map(|t| utils::clamp(t, 0., 1.))
+	}
+
+	fn spawn_objects(
+		&self, strength: i32, rng: &mut impl Rng, world: &mut hecs::World,
+	) -> Result<()>
+	{
+		for (i, p) in self.stars.iter().enumerate()
+		{
+			spawn_star(*p, i, world)?;
+		}
+
+		for (i, p) in self.buildings[..self.population as usize]
+			.iter()
+			.enumerate()
+		{
+			spawn_building(*p, i, world)?;
+		}
+
+		let choices = [(0, 20), (1, 20), (2, 10), (3, 10), (10, 3), (20, 1)];
+		let num = choices.choose_weighted(rng, |n_w| n_w.1).unwrap().0;
+		// Derelicts lurk away from the populated planets and the edges.
+		if self.population == 0 && strength > 0 && rng.gen_bool(DERELICT_CHANCE)
+		{
+			for _ in 0..utils::min(MAX_DERELICTS, strength)
+			{
+				let pos = Point2::new(
+					rng.gen_range(128.0..WIDTH - 128.),
+					rng.gen_range(64.0..HEIGHT - 128.),
+				);
+				if !nav::is_inside(&self.ground, pos) && self.collide(pos, NAV_MARGIN).is_none()
+				{
+					spawn_derelict(pos, world)?;
+				}
+			}
+		}
+		match self.gravity
+		{
+			Gravity::None =>
+			{
+				for _ in 0..num
+				{
+					spawn_car(
+						self.center
+							+ Vector2::new(
+								rng.gen_range(-256.0..256.0),
+								rng.gen_range(-256.0..256.0),
+							),
+						rng,
+						world,
+					)?;
+				}
+				// Keep away from the edges, where the player comes in.
+				let num_hazards = utils::min(MAX_HAZARDS, HAZARDS_PER_STRENGTH * strength);
+				for _ in 0..num_hazards
+				{
+					spawn_hazard(
+						Point2::new(
+							rng.gen_range(128.0..WIDTH - 128.),
+							rng.gen_range(128.0..HEIGHT - 128.),
+						),
+						rng,
+						world,
+					)?;
+				}
+			}
+			Gravity::Center(_) =>
+			{
+				for _ in 0..num
+				{
+					let theta = rng.gen_range(0.0..2.0 * utils::PI);
+					let r = 256.;
+					spawn_car(
+						self.center + Vector2::new(r * theta.cos(), r * theta.sin()),
+						rng,
+						world,
+					)?;
+				}
+			}
+			Gravity::Down(_) =>
+			{
+				for _ in 0..num
+				{
+					spawn_car(
+						Point2::new(rng.gen_range(-256.0..256.0), rng.gen_range(0.0..256.0)),
+						rng,
+						world,
+					)?;
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum State
+{
+	Game,
+	Victory,
+	Defeat,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Upgrade
+{
+	Thrust,
+	Rotation,
+	Landing,
+	Capacity,
+	Hull,
+}
+
+impl Upgrade
+{
+	pub const ALL: [Upgrade; 5] = [
+		Upgrade::Thrust,
+		Upgrade::Rotation,
+		Upgrade::Landing,
+		Upgrade::Capacity,
+		Upgrade::Hull,
+	];
+
+	pub fn name(self) -> &'static str
+	{
+		match self
+		{
+			Upgrade::Thrust => "Thrust",
+			Upgrade::Rotation => "Rotation",
+			Upgrade::Landing => "Landing Gear",
+			Upgrade::Capacity => "Train Capacity",
+			Upgrade::Hull => "Hull",
+		}
+	}
+}
+
+/// The levels of the upgrades bought in the shop.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Upgrades
+{
+	levels: [i32; 5],
+}
+
+impl Upgrades
+{
+	pub fn level(&self, upgrade: Upgrade) -> i32
+	{
+		self.levels[upgrade as usize]
+	}
+
+	/// Multiplier for an upgraded quantity, `per_level` better for each level bought.
+	fn scale(&self, upgrade: Upgrade, per_level: f32) -> f32
+	{
+		1. + per_level * self.level(upgrade) as f32
+	}
+}
+
+/// The player input for a single tick.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Input
+{
+	pub left: bool,
+	pub right: bool,
+	pub thrust: bool,
+}
+
+/// Something that happened during a tick of `Map::logic`. Sound, HUD messages and statistics
+/// are all driven by these.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GameEvent
+{
+	/// The ship was lost on an earlier tick and a new one has been spawned.
+	PlayerRespawned
+	{
+		score: i32,
+	},
+	CarPickedUp,
+	CarLost,
+	CarDelivered,
+	/// A landing on a populated planet that unloaded the train.
+	TrainDelivered
+	{
+		num_cars: i32,
+	},
+	PopulationIncreased
+	{
+		cell: usize,
+		amount: i32,
+	},
+	/// Delivered supplies cured some of the infected.
+	InfectionReduced
+	{
+		cell: usize,
+		amount: i32,
+	},
+	/// Delivered vaccines made some of the population immune.
+	PopulationImmunised
+	{
+		cell: usize,
+		amount: i32,
+	},
+	/// Delivered research samples, counted towards the next day's research.
+	SamplesDelivered
+	{
+		research: i32,
+	},
+	ContractPosted(Contract),
+	ContractCompleted
+	{
+		contract: Contract,
+		score: i32,
+	},
+	/// The deadline passed, and the planet lost some of its population.
+	ContractFailed(Contract),
+	/// A delivered car has been turned into score.
+	SupplyScored
+	{
+		pos: Point2<f32>,
+		multiplier: f32,
+		score: i32,
+	},
+	Explosion
+	{
+		pos: Point2<f32>,
+	},
+	/// The ship survived an impact.
+	ShipDamaged
+	{
+		damage: f32,
+	},
+	CellEntered
+	{
+		cell: usize,
+		populated: bool,
+	},
+	DayAdvanced
+	{
+		day: i32,
+	},
+	/// Tutorial hints and story beats, with their `{placeholders}` left in.
+	StoryMessage(String),
+	Sound(String),
+	PlanetInfected
+	{
+		cell: usize,
+		wiped_out: bool,
+	},
+	Victory,
+	Defeat,
+}
+
+pub struct Map
+{
+	pub name: String,
+	pub seed: u64,
+	pub world: hecs::World,
+	pub cells: Vec<MapCell>,
+	pub cell_pos: Point2<usize>,
+	pub layout: SectorLayout,
+	pub wormholes: Vec<Wormhole>,
+	pub difficulty: Difficulty,
+	pub player: hecs::Entity,
+	pub player_hull: parts::HullDef,
+	pub player_engine: parts::EngineDef,
+	rng: StdRng,
+	pub tick: i64,
+	pub score: i32,
+	pub target_score: i32,
+	pub day: i32,
+	pub research: i32,
+	pub strength: i32,
+	pub state: State,
+	/// Set by the story once the cure is found.
+	pub victory_pending: bool,
+	/// Research from delivered samples, added on the next day.
+	pub bonus_research: i32,
+	pub contracts: Vec<Contract>,
+	pub upgrades: Upgrades,
+	/// Whether the ship rested on the ground during the last tick.
+	landed: bool,
+	nav: nav::NavGrid,
+	story: story::Story,
+	pub start_planets: i32,
+	pub start_pop: i32,
+}
+
+/// How the edges of the sector connect to each other.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Topology
+{
+	/// Wraps around in both directions.
+	Torus,
+	/// Wraps around horizontally, with walls at the top and bottom.
+	Cylinder,
+	/// Walls on all sides.
+	Bounded,
+	/// Walls on all sides, but some of the outer edges lead to other cells.
+	Wormholes,
+}
+
+impl Topology
+{
+	pub fn from_str(s: &str) -> Option<Self>
+	{
+		match s
+		{
+			"torus" => Some(Topology::Torus),
+			"cylinder" => Some(Topology::Cylinder),
+			"bounded" => Some(Topology::Bounded),
+			"wormholes" => Some(Topology::Wormholes),
+			_ => None,
+		}
+	}
+
+	pub fn to_str(self) -> &'static str
+	{
+		match self
+		{
+			Topology::Torus => "torus",
+			Topology::Cylinder => "cylinder",
+			Topology::Bounded => "bounded",
+			Topology::Wormholes => "wormholes",
+		}
+	}
+}
+
+/// The dimensions and topology of the grid of cells.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SectorLayout
+{
+	pub width: usize,
+	pub height: usize,
+	pub topology: Topology,
+}
+
+impl Default for SectorLayout
+{
+	fn default() -> Self
+	{
+		Self {
+			width: 7,
+			height: 7,
+			topology: Topology::Torus,
+		}
+	}
+}
+
+impl SectorLayout
+{
+	/// Largest supported width or height.
+	pub const MAX_SIZE: usize = 100;
+
+	pub fn valid_size(size: usize) -> bool
+	{
+		(1..=Self::MAX_SIZE).contains(&size)
+	}
+
+	pub fn num_cells(&self) -> usize
+	{
+		self.width * self.height
+	}
+
+	pub fn cell_idx(&self, cell_pos: Point2<usize>) -> usize
+	{
+		cell_pos.y * self.width + cell_pos.x
+	}
+
+	pub fn cell_pos(&self, idx: usize) -> Point2<usize>
+	{
+		Point2::new(idx % self.width, idx / self.width)
+	}
+
+	/// Where leaving `cell_pos` through the edge `dir` (0 = right, 1 = up, 2 = left, 3 = down)
+	/// leads, ignoring wormholes. `None` means there's a wall.
+	pub fn neighbour(&self, cell_pos: Point2<usize>, dir: i32) -> Option<Point2<usize>>
+	{
+		let (dx, dy) = [(1, 0), (0, -1), (-1, 0), (0, 1)][dir as usize];
+		let wrap_x = self.topology == Topology::Torus || self.topology == Topology::Cylinder;
+		let wrap_y = self.topology == Topology::Torus;
+		let x = cell_pos.x as i32 + dx;
+		let y = cell_pos.y as i32 + dy;
+		let (w, h) = (self.width as i32, self.height as i32);
+		if (!wrap_x && (x < 0 || x >= w)) || (!wrap_y && (y < 0 || y >= h))
+		{
+			return None;
+		}
+		Some(Point2::new(
+			x.rem_euclid(w) as usize,
+			y.rem_euclid(h) as usize,
+		))
+	}
+}
+
+/// A one-way link from an outer edge of a cell to another cell.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Wormhole
+{
+	pub from: Point2<usize>,
+	pub dir: i32,
+	pub to: Point2<usize>,
+}
+
+/// A request from a planet for some cargo by a deadline.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Contract
+{
+	pub cell: usize,
+	pub cargo: comps::CargoKind,
+	pub amount: i32,
+	pub delivered: i32,
+	/// The last day the cargo can be delivered on.
+	pub deadline: i32,
+}
+
+fn make_wormholes(layout: &SectorLayout, rng: &mut impl Rng) -> Vec<Wormhole>
+{
+	let mut edges = vec![];
+	for idx in 0..layout.num_cells()
+	{
+		let cell_pos = layout.cell_pos(idx);
+		for dir in 0..4
+		{
+			if layout.neighbour(cell_pos, dir).is_none()
+			{
+				edges.push((cell_pos, dir));
+			}
+		}
+	}
+	edges.shuffle(rng);
+
+	// Link pairs of edges both ways, so every wormhole can be taken back.
+	let mut wormholes = vec![];
+	let num_links = utils::max(1, (layout.width + layout.height) / 4);
+	for pair in edges.chunks_exact(2).take(num_links)
+	{
+		let ((pos1, dir1), (pos2, dir2)) = (pair[0], pair[1]);
+		wormholes.push(Wormhole {
+			from: pos1,
+			dir: dir1,
+			to: pos2,
+		});
+		wormholes.push(Wormhole {
+			from: pos2,
+			dir: dir2,
+			to: pos1,
+		});
+	}
+	wormholes
+}
+
+/// A single entity of a saved `Map`. Connections refer to other entities by their index in
+/// `MapSave::entities`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EntitySave
+{
+	position: Option<comps::Position>,
+	velocity: Option<comps::Velocity>,
+	connection: Option<ConnectionSave>,
+	ship: bool,
+	affected_by_gravity: bool,
+	#[serde(default)]
+	hazard: bool,
+	#[serde(default)]
+	derelict: Option<comps::Derelict>,
+	car: Option<comps::Car>,
+	doodad: Option<comps::Doodad>,
+	solid: Option<comps::Solid>,
+	mass: Option<comps::Mass>,
+	car_corpse: Option<comps::CarCorpse>,
+	time_to_die: Option<comps::TimeToDie>,
+	sprite: Option<comps::Sprite>,
+	engine: Option<comps::Engine>,
+	#[serde(default)]
+	fuel: Option<comps::Fuel>,
+	#[serde(default)]
+	hull: Option<comps::Hull>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConnectionSave
+{
+	child: Option<usize>,
+}
+
+/// The full state of a run in progress.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MapSave
+{
+	name: String,
+	seed: u64,
+	rng_seed: u64,
+	cells: Vec<MapCell>,
+	cell_pos: Point2<usize>,
+	layout: SectorLayout,
+	wormholes: Vec<Wormhole>,
+	difficulty: Difficulty,
+	entities: Vec<EntitySave>,
+	player: usize,
+	player_hull: parts::HullDef,
+	player_engine: parts::EngineDef,
+	tick: i64,
+	score: i32,
+	target_score: i32,
+	day: i32,
+	research: i32,
+	strength: i32,
+	state: State,
+	victory_pending: bool,
+	#[serde(default)]
+	bonus_research: i32,
+	#[serde(default)]
+	contracts: Vec<Contract>,
+	#[serde(default)]
+	upgrades: Upgrades,
+	start_planets: i32,
+	start_pop: i32,
+}
+
+/// How a landing at the end of a predicted trajectory would go.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Landing
+{
+	Safe,
+	/// The ship survives, but is damaged.
+	TooFast,
+	/// The ship is destroyed.
+	Crash,
+}
+
+impl Landing
+{
+	pub fn name(&self) -> &'static str
+	{
+		match self
+		{
+			Landing::Safe => "Safe",
+			Landing::TooFast => "Too fast",
+			Landing::Crash => "Crash",
+		}
+	}
+}
+
+/// Where the ship will fly if left to coast, ignoring the train.
+#[derive(Clone, Debug)]
+pub struct Trajectory
+{
+	pub points: Vec<Point2<f32>>,
+	/// Where the ship hits the ground, if it does.
+	pub impact: Option<(Point2<f32>, Landing)>,
+}
+
+/// Damage of an object hitting the ground. `dot` is how level the ground is, as returned by
+/// `MapCell::collide`.
+fn impact_damage(speed: f32, max_vel: f32, dot: f32, is_car: bool) -> f32
+{
+	// Clean landings of the ship are free, everything else scales with the speed and the
+	// steepness of the ground.
+	let mut damage = 0.;
+	if is_car || speed > max_vel || dot < 0.9
+	{
+		let speed_f = speed / max_vel;
+		damage = IMPACT_DAMAGE * speed_f * speed_f * (1. + 4. * (1. - dot));
+	}
+	if is_car
+	{
+		damage += SCRAPE_DAMAGE * utils::DT;
+	}
+	damage
+}
+
+/// Applies the controls to a ship.
+fn steer(
+	input: &Input, thrust: f32, rotation_rate: f32, position: &mut comps::Position,
+	velocity: &mut comps::Velocity, engine: &mut comps::Engine,
+)
+{
+	let right_left = input.right as i32 as f32 - input.left as i32 as f32;
+	position.dir += rotation_rate * utils::DT * right_left;
+	let v = Rotation2::new(position.dir) * Vector2::new(1., 0.);
+	velocity.pos += v * utils::DT * thrust * input.thrust as i32 as f32;
+	engine.on = input.thrust;
+}
+
+/// Picks the controls that take a derelict towards its waypoint, fighting the acceleration
+/// due to the gravity field.
+fn derelict_input(
+	position: &comps::Position, velocity: &comps::Velocity, waypoint: Point2<f32>,
+	accel: Vector2<f32>,
+) -> Input
+{
+	let to_waypoint = waypoint - position.pos;
+	let want_vel = if to_waypoint.norm() > 1.
+	{
+		to_waypoint.normalize() * DERELICT_SPEED
+	}
+	else
+	{
+		Vector2::new(0., 0.)
+	};
+	let want_accel = 2. * (want_vel - velocity.pos) - accel;
+	let want_dir = want_accel.y.atan2(want_accel.x);
+	let turn = (want_dir - position.dir + utils::PI).rem_euclid(2. * utils::PI) - utils::PI;
+	Input {
+		left: turn < -0.1,
+		right: turn > 0.1,
+		thrust: turn.abs() < 0.6 && want_accel.norm() > 5.,
+	}
+}
+
+pub fn get_total_pop(cells: &[MapCell]) -> i32
+{
+	let mut ret = 0;
+	for cell in &cells[..]
+	{
+		ret += cell.population;
+	}
+	ret
+}
+
+impl Map
+{
+	pub fn new(
+		seed: u64, layout: SectorLayout, difficulty: Difficulty, player_hull: parts::HullDef,
+		player_engine: parts::EngineDef,
+	) -> Result<Self>
+	{
+		let mut world = hecs::World::new();
+		let player = spawn_ship(
+			&player_hull,
+			&player_engine,
+			Point2::new(WIDTH / 2., 50.),
+			-utils::PI / 2.,
+			&mut world,
+		)?;
+
+		let mut rng = StdRng::seed_from_u64(seed);
+
+		let mut names: Vec<_> = [
+			"Bootus", "Bootset", "Albus", "Akyor", "Choron", "Kratus", "Abeles", "Aralor", "Kenji",
+			"Jeren", "Gehen", "Multis", "X8532", "X532", "Wrandor", "Les-Lase", "Wender",
+			"Minimus", "Drator", "Huru", "Klam", "Meled", "Tuts", "Qudro", "Merder", "Joo", "Zood",
+			"Caestus", "Der", "Eol", "Iolus",
+		]
+		.iter()
+		.map(|s| s.to_string())
+		.collect();
+		names.shuffle(&mut rng);
+
+		let mut cells = vec![];
+		let mut planets = 0;
+		for _ in 0..layout.num_cells()
+		{
+			let cell = MapCell::new(&mut names, &mut rng);
+			if cell.population > 0
+			{
+				planets += 1;
+			}
+			cells.push(cell);
+		}
+
+		let total_pop = get_total_pop(&cells);
+		cells[0].spawn_objects(difficulty.strength(1), &mut rng, &mut world)?;
+		let nav = nav::NavGrid::new(&cells[0].ground, WIDTH, HEIGHT, NAV_MARGIN);
+		let wormholes = if layout.topology == Topology::Wormholes
+		{
+			make_wormholes(&layout, &mut rng)
+		}
+		else
+		{
+			vec![]
+		};
+
+		Ok(Self {
+			name: format!("{} Sector", names.pop().unwrap_or("Bratus".to_string())),
+			seed: seed,
+			world: world,
+			cells: cells,
+			cell_pos: Point2::new(0, 0),
+			layout: layout,
+			wormholes: wormholes,
+			difficulty: difficulty,
+			player: player,
+			player_hull: player_hull,
+			player_engine: player_engine,
+			rng: rng,
+			tick: 0,
+			score: 0,
+			target_score: 0,
+			day: 0,
+			research: 0,
+			strength: difficulty.strength(1),
+			state: State::Game,
+			victory_pending: false,
+			bonus_research: 0,
+			contracts: vec![],
+			upgrades: Upgrades::default(),
+			landed: false,
+			nav: nav,
+			story: story::Story::load(story::STORY_FILE)?,
+			start_pop: total_pop,
+			start_planets: planets,
+		})
+	}
+
+	/// Captures the state of the run. The RNG state can't be serialized directly, so it gets
+	/// reseeded here, which keeps this map and a map loaded from the save in lockstep.
+	pub fn save(&mut self) -> Result<MapSave>
+	{
+		let rng_seed = self.rng.gen();
+		self.rng = StdRng::seed_from_u64(rng_seed);
+
+		let entities: Vec<_> = self.world.iter().map(|e| e.entity()).collect();
+		let entity_to_idx: HashMap<_, _> =
+			entities.iter().enumerate().map(|(i, &e)| (e, i)).collect();
+
+		let mut entity_saves = Vec::with_capacity(entities.len());
+		for &e in &entities
+		{
+			let e = self.world.entity(e)?;
+			entity_saves.push(EntitySave {
+				position: e.get::<&comps::Position>().map(|c| *c),
+				velocity: e.get::<&comps::Velocity>().map(|c| *c),
+				connection: e.get::<&comps::Connection>().map(|c| ConnectionSave {
+					child: c.child.and_then(|child| entity_to_idx.get(&child).copied()),
+				}),
+				ship: e.has::<comps::Ship>(),
+				affected_by_gravity: e.has::<comps::AffectedByGravity>(),
+				hazard: e.has::<comps::Hazard>(),
+				derelict: e.get::<&comps::Derelict>().map(|c| *c),
+				car: e.get::<&comps::Car>().map(|c| *c),
+				doodad: e.get::<&comps::Doodad>().map(|c| (*c).clone()),
+				solid: e.get::<&comps::Solid>().map(|c| *c),
+				mass: e.get::<&comps::Mass>().map(|c| *c),
+				car_corpse: e.get::<&comps::CarCorpse>().map(|c| *c),
+				time_to_die: e.get::<&comps::TimeToDie>().map(|c| *c),
+				sprite: e.get::<&comps::Sprite>().map(|c| (*c).clone()),
+				engine: e.get::<&comps::Engine>().map(|c| (*c).clone()),
+				fuel: e.get::<&comps::Fuel>().map(|c| *c),
+				hull: e.get::<&comps::Hull>().map(|c| *c),
+			});
+		}
+
+		Ok(MapSave {
+			name: self.name.clone(),
+			seed: self.seed,
+			rng_seed: rng_seed,
+			cells: self.cells.clone(),
+			cell_pos: self.cell_pos,
+			layout: self.layout,
+			wormholes: self.wormholes.clone(),
+			difficulty: self.difficulty,
+			entities: entity_saves,
+			player: *entity_to_idx
+				.get(&self.player)
+				.ok_or("Player is missing".to_string())?,
+			player_hull: self.player_hull.clone(),
+			player_engine: self.player_engine.clone(),
+			tick: self.tick,
+			score: self.score,
+			target_score: self.target_score,
+			day: self.day,
+			research: self.research,
+			strength: self.strength,
+			state: self.state,
+			victory_pending: self.victory_pending,
+			bonus_research: self.bonus_research,
+			contracts: self.contracts.clone(),
+			upgrades: self.upgrades,
+			start_planets: self.start_planets,
+			start_pop: self.start_pop,
+		})
+	}
+
+	pub fn load(save: MapSave) -> Result<Self>
+	{
+		let mut world = hecs::World::new();
+		let mut entities = Vec::with_capacity(save.entities.len());
+		for entity_save in &save.entities
+		{
+			let mut builder = hecs::EntityBuilder::new();
+			if let Some(c) = entity_save.position
+			{
+				builder.add(c);
+			}
+			if let Some(c) = entity_save.velocity
+			{
+				builder.add(c);
+			}
+			if entity_save.connection.is_some()
+			{
+				builder.add(comps::Connection { child: None });
+			}
+			if entity_save.ship
+			{
+				builder.add(comps::Ship);
+			}
+			if entity_save.affected_by_gravity
+			{
+				builder.add(comps::AffectedByGravity);
+			}
+			if entity_save.hazard
+			{
+				builder.add(comps::Hazard);
+			}
+			if let Some(c) = entity_save.derelict
+			{
+				builder.add(c);
+			}
+			if let Some(c) = entity_save.car
+			{
+				builder.add(c);
+			}
+			if let Some(c) = entity_save.doodad.clone()
+			{
+				builder.add(c);
+			}
+			if let Some(c) = entity_save.solid
+			{
+				builder.add(c);
+			}
+			if let Some(c) = entity_save.mass
+			{
+				builder.add(c);
+			}
+			if let Some(c) = entity_save.car_corpse
+			{
+				builder.add(c);
+			}
+			if let Some(c) = entity_save.time_to_die
+			{
+				builder.add(c);
+			}
+			if let Some(c) = entity_save.sprite.clone()
+			{
+				builder.add(c);
+			}
+			if let Some(c) = entity_save.engine.clone()
+			{
+				builder.add(c);
+			}
+			if let Some(c) = entity_save.fuel
+			{
+				builder.add(c);
+			}
+			if let Some(c) = entity_save.hull
+			{
+				builder.add(c);
+			}
+			entities.push(world.spawn(builder.build()));
+		}
+
+		// Now that all entities exist, restore the trains.
+		for (entity_save, &e) in save.entities.iter().zip(entities.iter())
+		{
+			if let Some(ConnectionSave { child: Some(child) }) = entity_save.connection
+			{
+				let child = *entities
+					.get(child)
+					.ok_or(format!("Invalid connection: {}", child))?;
+				world.get::<&mut comps::Connection>(e)?.child = Some(child);
+			}
+		}
+
+		let mut cells = save.cells;
+		for cell in &mut cells
+		{
+			if cell.field == GravityField::default()
+			{
+				cell.field = GravityField::new(cell.gravity, cell.center);
+			}
+		}
+		let nav = nav::NavGrid::new(
+			&cells[save.layout.cell_idx(save.cell_pos)].ground,
+			WIDTH,
+			HEIGHT,
+			NAV_MARGIN,
+		);
+		Ok(Self {
+			name: save.name,
+			seed: save.seed,
+			world: world,
+			cells: cells,
+			cell_pos: save.cell_pos,
+			layout: save.layout,
+			wormholes: save.wormholes,
+			difficulty: save.difficulty,
+			player: *entities
+				.get(save.player)
+				.ok_or(format!("Invalid player: {}", save.player))?,
+			player_hull: save.player_hull,
+			player_engine: save.player_engine,
+			rng: StdRng::seed_from_u64(save.rng_seed),
+			tick: save.tick,
+			score: save.score,
+			target_score: save.target_score,
+			day: save.day,
+			research: save.research,
+			strength: save.strength,
+			state: save.state,
+			victory_pending: save.victory_pending,
+			bonus_research: save.bonus_research,
+			contracts: save.contracts,
+			upgrades: save.upgrades,
+			landed: false,
+			nav: nav,
+			story: story::Story::load(story::STORY_FILE)?,
+			start_planets: save.start_planets,
+			start_pop: save.start_pop,
+		})
+	}
+
+	pub fn cell(&self) -> &MapCell
+	{
+		&self.cells[self.layout.cell_idx(self.cell_pos)]
+	}
+
+	/// Where leaving `cell_pos` through the edge `dir` leads, taking wormholes into account.
+	pub fn neighbour(&self, cell_pos: Point2<usize>, dir: i32) -> Option<Point2<usize>>
+	{
+		self.wormholes
+			.iter()
+			.find(|w| w.from == cell_pos && w.dir == dir)
+			.map(|w| w.to)
+			.or_else(|| self.layout.neighbour(cell_pos, dir))
+	}
+
+	/// The edge to leave `from` through to get to `to`, if they're neighbours.
+	pub fn exit_dir(&self, from: Point2<usize>, to: Point2<usize>) -> Option<i32>
+	{
+		(0..4).find(|&dir| self.neighbour(from, dir) == Some(to))
+	}
+
+	/// The shortest sequence of cells from the current cell to `to`, both included.
+	pub fn route(&self, to: Point2<usize>) -> Vec<Point2<usize>>
+	{
+		if to == self.cell_pos
+		{
+			return vec![to];
+		}
+		let to_node = |p: Point2<usize>| Point2::new(p.x as i32, p.y as i32);
+		let size = utils::max(self.layout.width, self.layout.height);
+		let mut astar = astar::AStarContext::new(size);
+		let mut path = astar.solve_graph(
+			to_node(self.cell_pos),
+			to_node(to),
+			|node| {
+				(0..4)
+					.filter_map(|dir| {
+						self.neighbour(Point2::new(node.x as usize, node.y as usize), dir)
+					})
+					.map(|p| (to_node(p), 1.))
+					.collect()
+			},
+			|_, _| 0.,
+		);
+		path.reverse();
+		path.into_iter()
+			.map(|p| Point2::new(p.x as usize, p.y as usize))
+			.collect()
+	}
+
+	/// The populated cell with the fewest healthy people, other than the current one.
+	pub fn neediest_cell(&self) -> Option<Point2<usize>>
+	{
+		self.cells
+			.iter()
+			.enumerate()
+			.map(|(i, cell)| (self.layout.cell_pos(i), cell))
+			.filter(|&(cell_pos, cell)| cell.population > 0 && cell_pos != self.cell_pos)
+			.min_by_key(|&(_, cell)| (cell.population - cell.infected, -cell.infected))
+			.map(|(cell_pos, _)| cell_pos)
+	}
+
+	pub fn time(&self) -> f64
+	{
+		self.tick as f64 * utils::DT as f64
+	}
+
+	/// Applies the effects of the story beats whose trigger fires. Returns whether any did.
+	fn run_story(
+		&mut self, fires: impl Fn(&story::Trigger) -> bool, events: &mut Vec<GameEvent>,
+	) -> bool
+	{
+		let effects = self.story.effects(fires);
+		for effect in &effects
+		{
+			match effect
+			{
+				story::StoryEffect::Message(message) =>
+				{
+					events.push(GameEvent::StoryMessage(message.clone()));
+				}
+				story::StoryEffect::Sound(sound) => events.push(GameEvent::Sound(sound.clone())),
+				story::StoryEffect::Strength(strength) =>
+				{
+					self.strength = self.difficulty.strength(*strength)
+				}
+				story::StoryEffect::Victory => self.victory_pending = true,
+			}
+		}
+		!effects.is_empty()
+	}
+
+	/// Advances the epidemic in every cell by a day. Returns whether a planet was wiped out.
+	fn spread_disease(&mut self, events: &mut Vec<GameEvent>) -> bool
+	{
+		let infected: Vec<_> = self.cells.iter().map(|c| c.infected).collect();
+		let pressures: Vec<_> = (0..self.cells.len())
+			.map(|idx| {
+				let mut pressure = infected[idx] as f32;
+				for dir in 0..4
+				{
+					if let Some(pos) = self.neighbour(self.layout.cell_pos(idx), dir)
+					{
+						pressure += SPREAD_RATE * infected[self.layout.cell_idx(pos)] as f32;
+					}
+				}
+				pressure
+			})
+			.collect();
+		let infection_rate = utils::min(1., INFECTION_RATE * self.strength as f32);
+
+		let mut planet_lost = false;
+		for (idx, cell) in self.cells.iter_mut().enumerate()
+		{
+			if cell.population == 0
+			{
+				continue;
+			}
+			let was_infected = cell.infected > 0;
+			let p = 1. - (1. - infection_rate).powf(pressures[idx]);
+			let mut new_infected = 0;
+			for _ in 0..cell.susceptible()
+			{
+				new_infected += self.rng.gen_bool(p as f64) as i32;
+			}
+			let mut deaths = 0;
+			let mut recoveries = 0;
+			for _ in 0..cell.infected
+			{
+				if self.rng.gen_bool(MORTALITY as f64)
+				{
+					deaths += 1;
+				}
+				else if self.rng.gen_bool(RECOVERY_RATE as f64)
+				{
+					recoveries += 1;
+				}
+			}
+			let mut immunity_lost = 0;
+			for _ in 0..cell.recovered
+			{
+				immunity_lost += self.rng.gen_bool(IMMUNITY_LOSS as f64) as i32;
+			}
+
+			cell.infected += new_infected - deaths - recoveries;
+			cell.recovered += recoveries - immunity_lost;
+			cell.population -= deaths;
+			let wiped_out = cell.population == 0;
+			planet_lost |= wiped_out;
+			// Only report the start and the end of an outbreak.
+			if wiped_out || (!was_infected && cell.infected > 0)
+			{
+				events.push(GameEvent::PlanetInfected {
+					cell: idx,
+					wiped_out: wiped_out,
+				});
+			}
+		}
+		planet_lost
+	}
+
+	pub fn thrust(&self) -> f32
+	{
+		self.player_engine.thrust * self.upgrades.scale(Upgrade::Thrust, 0.2)
+	}
+
+	pub fn rotation_rate(&self) -> f32
+	{
+		self.player_hull.turn_rate * self.upgrades.scale(Upgrade::Rotation, 0.2)
+	}
+
+	/// Fastest safe landing speed.
+	pub fn max_vel(&self) -> f32
+	{
+		self.difficulty.max_vel * self.upgrades.scale(Upgrade::Landing, 0.15)
+	}
+
+	pub fn train_capacity(&self) -> i32
+	{
+		TRAIN_CAPACITY + 2 * self.upgrades.level(Upgrade::Capacity)
+	}
+
+	fn max_hull(&self) -> f32
+	{
+		SHIP_HULL * self.upgrades.scale(Upgrade::Hull, 0.25)
+	}
+
+	/// Integrates the motion of the ship in the gravity field of the current cell, until it hits
+	/// the ground, leaves the cell or `TRAJECTORY_TIME` runs out.
+	pub fn predict_trajectory(&self) -> Option<Trajectory>
+	{
+		let mut q = self
+			.world
+			.query_one::<(
+				&comps::Position,
+				&comps::Velocity,
+				&comps::Solid,
+				&comps::Hull,
+			)>(self.player)
+			.ok()?;
+		let (position, velocity, solid, hull) = q.get()?;
+		let cell = self.cell();
+		let mut pos = position.pos;
+		let mut vel = velocity.pos;
+		let mut trajectory = Trajectory {
+			points: vec![pos],
+			impact: None,
+		};
+		for _ in 0..(TRAJECTORY_TIME / utils::DT) as i32
+		{
+			vel += cell.acceleration(pos, vel) * utils::DT;
+			let new_pos = pos + vel * utils::DT;
+			if let Some((toi, (dot, _, _))) = cell.sweep(pos, new_pos, solid.size)
+			{
+				pos += (new_pos - pos) * toi;
+				let damage = impact_damage(vel.norm(), self.max_vel(), dot, false);
+				let landing = if damage >= hull.health
+				{
+					Landing::Crash
+				}
+				else if damage > 0.
+				{
+					Landing::TooFast
+				}
+				else
+				{
+					Landing::Safe
+				};
+				trajectory.points.push(pos);
+				trajectory.impact = Some((pos, landing));
+				break;
+			}
+			pos = new_pos;
+			trajectory.points.push(pos);
+			if pos.x < 0. || pos.y < 0. || pos.x > WIDTH || pos.y > HEIGHT
+			{
+				break;
+			}
+		}
+		Some(trajectory)
+	}
+
+	/// The shop is open while the ship is landed on a populated planet.
+	pub fn can_shop(&self) -> bool
+	{
+		self.landed && self.cell().population > 0 && self.world.contains(self.player)
+	}
+
+	/// Cost of the next level of an upgrade, if there is one.
+	pub fn upgrade_cost(&self, upgrade: Upgrade) -> Option<i32>
+	{
+		let level = self.upgrades.level(upgrade);
+		if level < MAX_UPGRADE_LEVEL
+		{
+			Some(UPGRADE_COST * (level + 1))
+		}
+		else
+		{
+			None
+		}
+	}
+
+	/// Spends score on the next level of an upgrade. Returns whether it was bought.
+	pub fn buy_upgrade(&mut self, upgrade: Upgrade) -> bool
+	{
+		let cost = match self.upgrade_cost(upgrade)
+		{
+			Some(cost) if self.can_shop() && self.target_score >= cost => cost,
+			_ => return false,
+		};
+		self.score -= cost;
+		self.target_score -= cost;
+		let old_max_hull = self.max_hull();
+		self.upgrades.levels[upgrade as usize] += 1;
+		if upgrade == Upgrade::Hull
+		{
+			let max_hull = self.max_hull();
+			if let Ok(mut hull) = self.world.get::<&mut comps::Hull>(self.player)
+			{
+				hull.max_health = max_hull;
+				hull.health += max_hull - old_max_hull;
+			}
+		}
+		true
+	}
+
+	/// Fails the contracts past their deadline and posts new ones. Returns whether a planet was
+	/// wiped out.
+	fn update_contracts(&mut self, events: &mut Vec<GameEvent>) -> bool
+	{
+		let mut planet_lost = false;
+		let mut i = 0;
+		while i < self.contracts.len()
+		{
+			let contract = self.contracts[i];
+			let cell = &mut self.cells[contract.cell];
+			if cell.population == 0
+			{
+				self.contracts.remove(i);
+			}
+			else if self.day > contract.deadline
+			{
+				self.contracts.remove(i);
+				cell.lose_population(1);
+				planet_lost |= cell.population == 0;
+				events.push(GameEvent::ContractFailed(contract));
+			}
+			else
+			{
+				i += 1;
+			}
+		}
+
+		if self.contracts.len() < MAX_CONTRACTS && self.rng.gen_bool(CONTRACT_CHANCE)
+		{
+			let candidates: Vec<_> = (0..self.cells.len())
+				.filter(|&idx| {
+					let cell = &self.cells[idx];
+					cell.population > 0
+						&& !cell.demand.is_empty()
+						&& !self.contracts.iter().any(|c| c.cell == idx)
+				})
+				.collect();
+			if let Some(&idx) = candidates.choose(&mut self.rng)
+			{
+				let amount = self.rng.gen_range(2..=4);
+				let contract = Contract {
+					cell: idx,
+					cargo: *self.cells[idx].demand.choose(&mut self.rng).unwrap(),
+					amount: amount,
+					delivered: 0,
+					deadline: self.day + amount + 3,
+				};
+				self.contracts.push(contract);
+				events.push(GameEvent::ContractPosted(contract));
+			}
+		}
+		planet_lost
+	}
+
+	/// Advances the simulation by one tick.
+	pub fn logic(&mut self, input: &Input) -> Result<Vec<GameEvent>>
+	{
+		let mut events = vec![];
+		if self.state != State::Game
+		{
+			return Ok(events);
+		}
+		self.tick += 1;
+		let time = self.time();
+		let mut to_die = vec![];
+
+		if self.tick == 1
+		{
+			self.run_story(|t| *t == story::Trigger::Day(0), &mut events);
+		}
+
+		// Player respawn.
+		if !self.world.contains(self.player)
+		{
+			self.player = spawn_ship(
+				&self.player_hull,
+				&self.player_engine,
+				Point2::new(WIDTH / 2., 50.),
+				-utils::PI / 2.,
+				&mut self.world,
+			)?;
+			self.world
+				.insert_one(self.player, comps::Hull::new(self.max_hull()))?;
+			self.target_score -= self.difficulty.crash_penalty;
+			events.push(GameEvent::PlayerRespawned {
+				score: -self.difficulty.crash_penalty,
+			});
+		}
+
+		// Score.
+		let delta = (utils::DT * (self.target_score - self.score) as f32) as i32;
+		self.score += delta;
+		if delta == 0 && self.score != self.target_score
+		{
+			self.score = self.target_score;
+		}
+
+		// Player input.
+		let want_left = input.left;
+		let want_right = input.right;
+		let want_thrust = input.thrust;
+
+		let thrust_power = self.thrust() / self.player_hull.mass;
+		let rotation_rate = self.rotation_rate();
+		if let Ok((position, velocity, engine, fuel)) = self.world.query_one_mut::<(
+			&mut comps::Position,
+			&mut comps::Velocity,
+			&mut comps::Engine,
+			Option<&mut comps::Fuel>,
+		)>(self.player)
+		{
+			let mut want_thrust = want_thrust;
+			if let Some(fuel) = fuel
+			{
+				want_thrust &= fuel.fuel > 0.;
+				if want_thrust
+				{
+					fuel.fuel = utils::max(0., fuel.fuel - utils::DT * fuel.use_rate);
+				}
+			}
+
+			let input = Input {
+				left: want_left,
+				right: want_right,
+				thrust: want_thrust,
+			};
+			steer(
+				&input,
+				thrust_power,
+				rotation_rate,
+				position,
+				velocity,
+				engine,
+			);
+		}
+
+		// Derelicts fly towards the closest part of the train.
+		let mut train = vec![];
+		let mut cur = Some(self.player);
+		while let Some(e) = cur
+		{
+			cur = None;
+			if let Ok(mut q) = self
+				.world
+				.query_one::<(&comps::Position, &comps::Connection)>(e)
+			{
+				if let Some((position, connection)) = q.get()
+				{
+					train.push(position.pos);
+					cur = connection.child;
+				}
+			}
+		}
+		let repath = self.tick % DERELICT_REPATH_TICKS == 0;
+		let cell = &self.cells[self.layout.cell_idx(self.cell_pos)];
+		for (_, (position, velocity, engine, derelict)) in self.world.query_mut::<(
+			&mut comps::Position,
+			&mut comps::Velocity,
+			&mut comps::Engine,
+			&mut comps::Derelict,
+		)>()
+		{
+			let target = train.iter().min_by(|a, b| {
+				(*a - position.pos)
+					.norm()
+					.partial_cmp(&(*b - position.pos).norm())
+					.unwrap()
+			});
+			if let (true, Some(&target)) = (repath, target)
+			{
+				let path = self.nav.path(position.pos, target);
+				// Aim a few nodes ahead to smooth out the grid.
+				derelict.waypoint = path.get(3).or(path.last()).copied().unwrap_or(target);
+			}
+			let input = derelict_input(
+				position,
+				velocity,
+				derelict.waypoint,
+				cell.acceleration(position.pos, velocity.pos),
+			);
+			steer(
+				&input,
+				DERELICT_THRUST,
+				DERELICT_ROTATION_RATE,
+				position,
+				velocity,
+				engine,
+			);
+		}
+
+		// Gravity, wind and drag.
+		let cell = &self.cells[self.layout.cell_idx(self.cell_pos)];
+		for (_, (position, velocity, _)) in self.world.query_mut::<(
+			&comps::Position,
+			&mut comps::Velocity,
+			&comps::AffectedByGravity,
+		)>()
+		{
+			velocity.pos += cell.acceleration(position.pos, velocity.pos) * utils::DT;
+		}
+
+		// Connection cleanup.
+		for (_, connection) in self.world.query::<&mut comps::Connection>().iter()
+		{
+			if let Some(child) = connection.child
+			{
+				if !self.world.contains(child)
+				{
+					connection.child = None;
+				}
+			}
+		}
+
+		// Train logic.
+		let mut children_to_move = vec![];
+		for (e, connection) in self.world.query::<&comps::Connection>().iter()
+		{
+			if let Some(child) = connection.child
+			{
+				children_to_move.push((e, child));
+			}
+		}
+
+		for &(parent, child) in &children_to_move
+		{
+			let mut q = self
+				.world
+				.query_many_mut::<(&comps::Position, &mut comps::Velocity, Option<&comps::Mass>), 2>(
+					[parent, child],
+				);
+			let [Ok((parent_position, parent_velocity, parent_mass)), Ok((child_position, child_velocity, child_mass))] =
+				&mut q
+			else
+			{
+				continue;
+			};
+			let mut dv = child_position.pos - parent_position.pos;
+			if dv == Vector2::new(0., 0.)
+			{
+				dv = Vector2::new(1., 0.);
+			}
+			let normal = dv.normalize();
+			// Limit the pull of cars that were just attached to the far end of the train.
+			let stretch = utils::clamp(
+				dv.norm() - TRAIN_LINK_LENGTH,
+				-TRAIN_LINK_LENGTH,
+				TRAIN_LINK_LENGTH,
+			);
+			let rel_vel = (child_velocity.pos - parent_velocity.pos).dot(&normal);
+			let force = -(TRAIN_STIFFNESS * stretch + TRAIN_DAMPING * rel_vel) * normal;
+			let parent_mass = parent_mass.map(|m| m.mass).unwrap_or(1.);
+			let child_mass = child_mass.map(|m| m.mass).unwrap_or(1.);
+			parent_velocity.pos -= force / parent_mass * utils::DT;
+			child_velocity.pos += force / child_mass * utils::DT;
+			child_velocity.dir = 0.;
+		}
+
+		// Physics.
+		let mut old_positions = HashMap::new();
+		for (e, (position, _)) in self
+			.world
+			.query::<(&comps::Position, &comps::Solid)>()
+			.iter()
+		{
+			old_positions.insert(e, position.pos);
+		}
+		for (_, (position, velocity)) in self
+			.world
+			.query_mut::<(&mut comps::Position, &mut comps::Velocity)>()
+		{
+			position.pos += velocity.pos * utils::DT;
+			position.dir += velocity.dir * utils::DT;
+		}
+		for (_, (position, _)) in self
+			.world
+			.query_mut::<(&mut comps::Position, &comps::Hazard)>()
+		{
+			position.pos.x = position.pos.x.rem_euclid(WIDTH);
+			position.pos.y = position.pos.y.rem_euclid(HEIGHT);
+		}
+
+		// Cars face the car in front of them.
+		for (parent, child) in children_to_move
+		{
+			let parent_pos = self.world.get::<&comps::Position>(parent)?.pos;
+			let mut child_position = self.world.get::<&mut comps::Position>(child)?;
+			let dv = child_position.pos - parent_pos;
+			if dv != Vector2::new(0., 0.)
+			{
+				child_position.dir = dv.y.atan2(dv.x);
+			}
+		}
+
+		// Object-object collision
+		let mut delete_tail = vec![];
+		let mut wrecked = vec![];
+		let max_vel = self.max_vel();
+		let mut broad_phase = broad_phase::BroadPhase::new(COLLISION_CELL_SIZE);
+		for (e, (position, solid)) in self
+			.world
+			.query::<(&comps::Position, &comps::Solid)>()
+			.iter()
+		{
+			broad_phase.insert(e, position.pos, solid.size, solid.kind);
+		}
+		for (e1, e2) in broad_phase.pairs()
+		{
+			let is_hazard = |e| self.world.get::<&comps::Hazard>(e).is_ok();
+			let is_derelict = |e| self.world.get::<&comps::Derelict>(e).is_ok();
+			let attack = if is_hazard(e1)
+			{
+				Some((e1, e2, HAZARD_DAMAGE))
+			}
+			else if is_hazard(e2)
+			{
+				Some((e2, e1, HAZARD_DAMAGE))
+			}
+			else if is_derelict(e1) && !is_derelict(e2)
+			{
+				Some((e1, e2, DERELICT_DAMAGE))
+			}
+			else if is_derelict(e2) && !is_derelict(e1)
+			{
+				Some((e2, e1, DERELICT_DAMAGE))
+			}
+			else
+			{
+				None
+			};
+			if let Some((attacker, victim, base_damage)) = attack
+			{
+				// The victim bounces off, and only takes damage while it is closing in.
+				let attacker_pos = self.world.get::<&comps::Position>(attacker)?.pos;
+				let attacker_vel = self.world.get::<&comps::Velocity>(attacker)?.pos;
+				let victim_pos = self.world.get::<&comps::Position>(victim)?.pos;
+				let mut victim_velocity = self.world.get::<&mut comps::Velocity>(victim)?;
+				let mut dv = victim_pos - attacker_pos;
+				if dv == Vector2::new(0., 0.)
+				{
+					dv = Vector2::new(1., 0.);
+				}
+				let normal = dv.normalize();
+				let rel_vel = (victim_velocity.pos - attacker_vel).dot(&normal);
+				if rel_vel >= 0.
+				{
+					continue;
+				}
+				victim_velocity.pos -= 2. * rel_vel * normal;
+				drop(victim_velocity);
+
+				let damage = base_damage * (1. - rel_vel / max_vel);
+				let explode = if let Ok(mut hull) = self.world.get::<&mut comps::Hull>(victim)
+				{
+					hull.health -= damage;
+					hull.health <= 0.
+				}
+				else
+				{
+					true
+				};
+				if explode && is_derelict(victim)
+				{
+					wrecked.push(victim);
+				}
+				else if explode
+				{
+					delete_tail.push((victim, true));
+				}
+				else if victim == self.player
+				{
+					events.push(GameEvent::ShipDamaged { damage: damage });
+				}
+				// Derelicts ram themselves to pieces.
+				if is_derelict(attacker)
+				{
+					wrecked.push(attacker);
+				}
+				continue;
+			}
+
+			let (ship, car) = if self.world.get::<&comps::Ship>(e1).is_ok()
+			{
+				(e1, e2)
+			}
+			else
+			{
+				(e2, e1)
+			};
+			if self.world.get::<&comps::Ship>(ship).is_ok()
+				&& Ok(false) == self.world.get::<&comps::Car>(car).map(|c| c.attached)
+			{
+				let mut tail = ship;
+				let mut num_cars = 0;
+				while let Some(new_tail) = self.world.get::<&comps::Connection>(tail)?.child
+				{
+					tail = new_tail;
+					num_cars += 1;
+				}
+				if num_cars >= self.train_capacity()
+				{
+					continue;
+				}
+				events.push(GameEvent::CarPickedUp);
+				self.world.get::<&mut comps::Connection>(tail)?.child = Some(car);
+				let mut car = self.world.get::<&mut comps::Car>(car)?;
+				car.attached = true;
+			}
+		}
+
+		// Ground collision.
+		let mut multiplier = 1.;
+		self.landed = false;
+		for (e, (position, velocity, solid)) in self
+			.world
+			.query::<(&mut comps::Position, &mut comps::Velocity, &comps::Solid)>()
+			.iter()
+		{
+			let old_pos = old_positions.get(&e).copied().unwrap_or(position.pos);
+			if let Some((toi, (dot, normal, ground_point))) =
+				self.cell().sweep(old_pos, position.pos, solid.size)
+			{
+				if self.world.get::<&comps::Derelict>(e).is_ok()
+				{
+					wrecked.push(e);
+					continue;
+				}
+				let contact_pos = old_pos + (position.pos - old_pos) * toi;
+				let mut dv = contact_pos - ground_point;
+				if dv == Vector2::new(0., 0.)
+				{
+					dv = Vector2::new(1., 0.);
+				}
+				position.pos = ground_point + dv * solid.size / dv.norm();
+				position.dir = normal.y.atan2(normal.x);
+
+				let is_ship = self.world.get::<&comps::Ship>(e).is_ok();
+				let is_car = self.world.get::<&comps::Car>(e).is_ok();
+				let speed = velocity.pos.norm();
+				if is_ship
+				{
+					let m = (max_vel - speed) / 5.;
+					multiplier = utils::max(1., 0.5 * (m / 0.5).round());
+					if self.cell().on_landing_pad(ground_point)
+					{
+						multiplier *= LANDING_PAD_BONUS;
+					}
+				}
+
+				let damage = impact_damage(speed, max_vel, dot, is_car);
+				let explode = if let Ok(mut hull) = self.world.get::<&mut comps::Hull>(e)
+				{
+					hull.health -= damage;
+					let explode = hull.health <= 0.;
+					if is_ship && !explode && self.cell().population > 0
+					{
+						self.landed = true;
+						hull.health =
+							utils::min(hull.max_health, hull.health + utils::DT * REPAIR_RATE);
+					}
+					explode
+				}
+				else
+				{
+					damage > 0.
+				};
+				if is_ship && !explode && damage >= 1.
+				{
+					events.push(GameEvent::ShipDamaged { damage: damage });
+				}
+				velocity.pos.x = 0.;
+				velocity.pos.y = 0.;
+
+				if is_ship && !explode && self.cell().refuels_at(ground_point)
+				{
+					if let Ok(mut fuel) = self.world.get::<&mut comps::Fuel>(e)
+					{
+						fuel.fuel = utils::min(fuel.max_fuel, fuel.fuel + utils::DT * REFUEL_RATE);
+					}
+				}
+				if explode || (is_ship && self.cell().population > 0)
+				{
+					delete_tail.push((e, explode));
+				}
+			}
+		}
+
+		let mut car_corpses = vec![];
+		let mut train_size = 0;
+		let mut explosions = vec![];
+		wrecked.sort();
+		wrecked.dedup();
+		for e in wrecked
+		{
+			explosions.push((true, 1.0, self.world.get::<&comps::Position>(e)?.pos));
+			to_die.push(e);
+		}
+		// An entity can hit several things in one tick, but its tail must only be removed once.
+		delete_tail.sort_by_key(|&(e, explode)| (e, !explode));
+		delete_tail.dedup_by_key(|&mut (e, _)| e);
+		for (e, explode) in delete_tail
+		{
+			let mut count = 0usize;
+			let mut tail = e;
+			loop
+			{
+				let mut q = self
+					.world
+					.query_one::<(&mut comps::Connection, &comps::Position)>(tail)?;
+				if let Some((connection, position)) = q.get()
+				{
+					// Hack.
+					if explode || tail != self.player
+					{
+						to_die.push(tail);
+					}
+
+					if explode && tail == self.player
+					{
+						explosions.push((true, 1.0, position.pos));
+					}
+
+					if let Some((car, sprite, hull)) = self
+						.world
+						.query_one::<(&comps::Car, &comps::Sprite, Option<&comps::Hull>)>(tail)?
+						.get()
+					{
+						count += 1;
+						if explode
+						{
+							events.push(GameEvent::CarLost);
+						}
+						else
+						{
+							train_size += 1;
+							events.push(GameEvent::CarDelivered);
+						}
+						car_corpses.push((
+							position.clone(),
+							sprite.clone(),
+							car.cargo,
+							hull.map(|h| h.fraction()).unwrap_or(1.),
+							time + count as f64 * 0.25,
+							explode,
+						));
+					}
+
+					if let Some(child) = connection.child
+					{
+						tail = child;
+					}
+					else
+					{
+						break;
+					}
+				}
+				else
+				{
+					break;
+				}
+			}
+		}
+		if train_size > 0
+		{
+			events.push(GameEvent::TrainDelivered {
+				num_cars: train_size,
+			});
+		}
+
+		let cell_idx = self.layout.cell_idx(self.cell_pos);
+		let (mut add_pop, mut cured, mut immunised, mut research) = (0, 0, 0, 0);
+		for (position, sprite, cargo, health, time_to_die, explode) in car_corpses
+		{
+			// Cargo the planet asks for scores double and has an effect on it. Damaged cargo
+			// scores less, and is no use to the planet once it's half gone.
+			let wanted = !explode && self.cells[cell_idx].demand.contains(&cargo);
+			let car_multiplier = if wanted { 2. * multiplier } else { multiplier };
+			spawn_car_corpse(
+				position,
+				sprite,
+				explode,
+				time_to_die,
+				car_multiplier * health,
+				&mut self.rng,
+				&mut self.world,
+			)?;
+
+			if explode
+			{
+				continue;
+			}
+			multiplier += 0.5;
+			if !wanted || health < 0.5
+			{
+				continue;
+			}
+			// Medicine and vaccines that have no one left to treat go towards growing the
+			// population, like food.
+			let cell = &mut self.cells[cell_idx];
+			match cargo
+			{
+				comps::CargoKind::Medicine if cell.infected > 0 =>
+				{
+					cell.infected -= 1;
+					cell.recovered += 1;
+					cured += 1;
+				}
+				comps::CargoKind::Vaccine if cell.susceptible() > 0 =>
+				{
+					cell.recovered += 1;
+					immunised += 1;
+				}
+				comps::CargoKind::Samples => research += SAMPLE_RESEARCH,
+				_ => add_pop += 1,
+			}
+			if let Some(contract) = self
+				.contracts
+				.iter_mut()
+				.find(|c| c.cell == cell_idx && c.cargo == cargo && c.delivered < c.amount)
+			{
+				contract.delivered += 1;
+			}
+		}
+
+		let mut i = 0;
+		while i < self.contracts.len()
+		{
+			let contract = self.contracts[i];
+			if contract.delivered < contract.amount
+			{
+				i += 1;
+				continue;
+			}
+			self.contracts.remove(i);
+			let score = CONTRACT_SCORE * contract.amount;
+			self.target_score += score;
+			self.bonus_research += CONTRACT_RESEARCH * contract.amount;
+			events.push(GameEvent::ContractCompleted {
+				contract: contract,
+				score: score,
+			});
+		}
+
+		if cured > 0
+		{
+			events.push(GameEvent::InfectionReduced {
+				cell: cell_idx,
+				amount: cured,
+			});
+		}
+		if immunised > 0
+		{
+			events.push(GameEvent::PopulationImmunised {
+				cell: cell_idx,
+				amount: immunised,
+			});
+		}
+		if research > 0
+		{
+			self.bonus_research += research;
+			events.push(GameEvent::SamplesDelivered { research: research });
+		}
+		if add_pop > 0
+		{
+			let cell = &mut self.cells[cell_idx];
+			let old_pop = cell.population;
+			cell.population = utils::min(9, cell.population + add_pop);
+			let diff = cell.population - old_pop;
+			if diff != 0
+			{
+				events.push(GameEvent::PopulationIncreased {
+					cell: cell_idx,
+					amount: diff,
+				});
+			}
+		}
+
+		// Car corpse
+		for (id, (position, car_corpse)) in self
+			.world
+			.query_mut::<(&comps::Position, &comps::CarCorpse)>()
+		{
+			if time > car_corpse.time_to_die
+			{
+				explosions.push((car_corpse.explode, car_corpse.multiplier, position.pos));
+				to_die.push(id);
+			}
+		}
+
+		for (explode, multiplier, pos) in explosions
+		{
+			if explode
+			{
+				events.push(GameEvent::Explosion { pos: pos });
+				spawn_explosion(pos, time, &mut self.world)?;
+			}
+			else
+			{
+				let score = (multiplier * 100.) as i32;
+				self.target_score += score;
+				events.push(GameEvent::SupplyScored {
+					pos: pos,
+					multiplier: multiplier,
+					score: score,
+				});
+				spawn_deliver(pos, time, &mut self.world)?;
+			}
+		}
+
+		// Transition
+		let mut dir_and_pos = None;
+		let mut exits = vec![];
+		if let Ok(position) = self.world.query_one_mut::<&comps::Position>(self.player)
+		{
+			if position.pos.x > WIDTH + 10.
+			{
+				exits.push(0);
+			}
+			if position.pos.y < -10.
+			{
+				exits.push(1);
+			}
+			if position.pos.x < -10.
+			{
+				exits.push(2);
+			}
+			if position.pos.y > HEIGHT + 10.
+			{
+				exits.push(3);
+			}
+		}
+		for dir in exits
+		{
+			if let Some(new_cell_pos) = self.neighbour(self.cell_pos, dir)
+			{
+				self.cell_pos = new_cell_pos;
+				let pos = self.world.get::<&comps::Position>(self.player)?.pos;
+				dir_and_pos = Some((dir, pos));
+			}
+			else
+			{
+				// Bounce off the wall.
+				let (position, velocity) = self
+					.world
+					.query_one_mut::<(&mut comps::Position, &mut comps::Velocity)>(self.player)
+					.unwrap();
+				match dir
+				{
+					0 =>
+					{
+						position.pos.x = WIDTH + 10.;
+						velocity.pos.x = -velocity.pos.x.abs();
+					}
+					1 =>
+					{
+						position.pos.y = -10.;
+						velocity.pos.y = velocity.pos.y.abs();
+					}
+					2 =>
+					{
+						position.pos.x = -10.;
+						velocity.pos.x = velocity.pos.x.abs();
+					}
+					3 =>
+					{
+						position.pos.y = HEIGHT + 10.;
+						velocity.pos.y = -velocity.pos.y.abs();
+					}
+					_ => unreachable!(),
+				}
+			}
+		}
+
+		let gravity = self.cell().gravity;
+		if let Some((dir, pos)) = dir_and_pos
+		{
+			events.push(GameEvent::CellEntered {
+				cell: self.layout.cell_idx(self.cell_pos),
+				populated: self.cell().population > 0,
+			});
+			let mut pop_indices = vec![];
+			let mut total_pop = 0;
+			for (i, cell) in self.cells.iter().enumerate()
+			{
+				if cell.population > 0
+				{
+					pop_indices.push(i);
+				}
+				total_pop += cell.population;
+			}
+			let old_research = self.research;
+			let old_day = self.day;
+			self.research += utils::max(
+				pop_indices.len() as i32 * self.difficulty.research_per_planet,
+				total_pop / self.difficulty.research_pop_divisor,
+			) + self.bonus_research;
+			self.bonus_research = 0;
+			self.day += 1;
+			events.push(GameEvent::DayAdvanced { day: self.day });
+
+			let mut special_day = false;
+			if self.victory_pending
+			{
+				self.state = State::Victory;
+				events.push(GameEvent::Victory);
+			}
+			else
+			{
+				let (day, research) = (self.day, self.research);
+				let difficulty = self.difficulty;
+				special_day = self.run_story(
+					|t| match *t
+					{
+						story::Trigger::Day(d) => old_day < d && day >= d,
+						story::Trigger::Research(r) =>
+						{
+							let r = difficulty.research_threshold(r);
+							old_research < r && research >= r
+						}
+						_ => false,
+					},
+					&mut events,
+				);
+			}
+
+			// New outbreaks.
+			if !special_day
+				&& self.rng.gen_bool(self.difficulty.disease_chance as f64)
+				&& self.strength > 0
+			{
+				if let Some(&idx) = pop_indices.choose(&mut self.rng)
+				{
+					let cell = &mut self.cells[idx];
+					let was_infected = cell.infected > 0;
+					cell.infected += utils::min(self.strength, cell.susceptible());
+					if !was_infected && cell.infected > 0
+					{
+						events.push(GameEvent::PlanetInfected {
+							cell: idx,
+							wiped_out: false,
+						});
+					}
+				}
+			}
+			let mut planet_lost = self.spread_disease(&mut events);
+			planet_lost |= self.update_contracts(&mut events);
+			let new_total_pop = get_total_pop(&self.cells);
+			if self.state == State::Game
+			{
+				self.run_story(
+					|t| match *t
+					{
+						story::Trigger::PopulationBelow(p) => total_pop >= p && new_total_pop < p,
+						story::Trigger::PlanetLost => planet_lost,
+						_ => false,
+					},
+					&mut events,
+				);
+			}
+			if new_total_pop == 0 && !pop_indices.is_empty()
+			{
+				self.state = State::Defeat;
+				events.push(GameEvent::Defeat);
+			}
+
+			let start_pos;
+			let reset_vel;
+			let delta;
+			match gravity
+			{
+				Gravity::None | Gravity::Center(_) =>
+				{
+					reset_vel = false;
+					match dir
+					{
+						0 =>
+						{
+							start_pos = Point2::new(0., pos.y);
+							delta = Vector2::new(-TRAIN_LINK_LENGTH, 0.);
+						}
+						1 =>
+						{
+							start_pos = Point2::new(pos.x, HEIGHT);
+							delta = Vector2::new(0., TRAIN_LINK_LENGTH);
+						}
+						2 =>
+						{
+							start_pos = Point2::new(WIDTH, pos.y);
+							delta = Vector2::new(TRAIN_LINK_LENGTH, 0.);
+						}
+						3 =>
+						{
+							start_pos = Point2::new(pos.x, 0.);
+							delta = Vector2::new(0., -TRAIN_LINK_LENGTH);
+						}
+						_ => unreachable!(),
+					}
+				}
+				Gravity::Down(_) =>
+				{
+					start_pos = Point2::new(WIDTH / 2., 0.);
+					delta = Vector2::new(0., -TRAIN_LINK_LENGTH);
+					reset_vel = true;
+				}
+			}
+			let mut tail = self.player;
+			let mut cur_pos = start_pos;
+			loop
+			{
+				let (position, velocity, connection) = self
+					.world
+					.query_one_mut::<(
+						&mut comps::Position,
+						&mut comps::Velocity,
+						&comps::Connection,
+					)>(tail)
+					.unwrap();
+				position.pos = cur_pos;
+				cur_pos += delta;
+				if reset_vel
+				{
+					velocity.pos = Vector2::new(0., 0.);
+					velocity.dir = 0.;
+					position.dir = -utils::PI / 2.;
+				}
+				if let Some(new_tail) = connection.child
+				{
+					tail = new_tail;
+				}
+				else
+				{
+					break;
+				}
+			}
+
+			for (e, car) in self.world.query_mut::<&comps::Car>()
+			{
+				if !car.attached
+				{
+					to_die.push(e);
+				}
+			}
+			for (e, _) in self.world.query_mut::<&comps::Doodad>()
+			{
+				to_die.push(e);
+			}
+			for (e, _) in self.world.query_mut::<&comps::Hazard>()
+			{
+				to_die.push(e);
+			}
+			for (e, _) in self.world.query_mut::<&comps::Derelict>()
+			{
+				to_die.push(e);
+			}
+			let cell_idx = self.layout.cell_idx(self.cell_pos);
+			self.nav = nav::NavGrid::new(&self.cells[cell_idx].ground, WIDTH, HEIGHT, NAV_MARGIN);
+			self.cells[cell_idx].spawn_objects(self.strength, &mut self.rng, &mut self.world)?;
+		}
+
+		// Time to die
+		for (id, time_to_die) in self.world.query_mut::<&comps::TimeToDie>()
+		{
+			if time > time_to_die.time_to_die
+			{
+				to_die.push(id);
+			}
+		}
+
+		// Remove dead entities
+		to_die.sort();
+		to_die.dedup();
+		for id in to_die
+		{
+			//println!("died {id:?}");
+			self.world.despawn(id)?;
+		}
+
+		Ok(events)
+	}
+}
+
+/// A map with nothing but the player, starting in an empty cell with the given planet and ground.
+#[cfg(test)]
+fn test_map(gravity: Gravity, ground: Vec<(f32, f32)>) -> Map
+{
+	let mut map = Map::new(
+		0,
+		SectorLayout::default(),
+		Difficulty::default(),
+		parts::HullDef::default(),
+		parts::EngineDef::default(),
+	)
+	.unwrap();
+	let others: Vec<_> = map
+		.world
+		.iter()
+		.map(|e| e.entity())
+		.filter(|&e| e != map.player)
+		.collect();
+	for e in others
+	{
+		map.world.despawn(e).unwrap();
+	}
+	let cell = &mut map.cells[0];
+	cell.gravity = gravity;
+	cell.field = GravityField::new(gravity, cell.center);
+	cell.population = 0;
+	cell.infected = 0;
+	cell.ground = ground;
+	map.nav = nav::NavGrid::new(&map.cells[0].ground, WIDTH, HEIGHT, NAV_MARGIN);
+	map
+}
+
+#[test]
+fn test_headless_logic()
+{
+	let mut map = Map::new(
+		0,
+		SectorLayout::default(),
+		Difficulty::default(),
+		parts::HullDef::default(),
+		parts::EngineDef::default(),
+	)
+	.unwrap();
+
+	let input = Input {
+		thrust: true,
+		..Input::default()
+	};
+	let events = map.logic(&input).unwrap();
+	assert!(matches!(events[..], [GameEvent::StoryMessage(_)]));
+	for _ in 1..600
+	{
+		map.logic(&input).unwrap();
+	}
+	assert_eq!(map.tick, 600);
+	assert!(map.world.contains(map.player));
+}
+
+#[test]
+fn test_deterministic_seed()
+{
+	let run = |seed| {
+		let mut map = Map::new(
+			seed,
+			SectorLayout::default(),
+			Difficulty::default(),
+			parts::HullDef::default(),
+			parts::EngineDef::default(),
+		)
+		.unwrap();
+		for i in 0..1200
+		{
+			let input = Input {
+				left: i % 200 < 20,
+				right: false,
+				thrust: i % 3 == 0,
+			};
+			map.logic(&input).unwrap();
+		}
+		let pos = map.world.get::<&comps::Position>(map.player).unwrap().pos;
+		let pops: Vec<_> = map.cells.iter().map(|c| c.population).collect();
+		(map.name.clone(), map.day, map.target_score, pops, pos)
+	};
+	assert_eq!(run(1234), run(1234));
+	assert_ne!(run(1234).3, run(4321).3);
+}
+
+#[test]
+fn test_save_load()
+{
+	let mut map = Map::new(
+		1234,
+		SectorLayout::default(),
+		Difficulty::default(),
+		parts::HullDef::default(),
+		parts::EngineDef::default(),
+	)
+	.unwrap();
+	let input = Input {
+		thrust: true,
+		..Input::default()
+	};
+	for _ in 0..100
+	{
+		map.logic(&input).unwrap();
+	}
+	let car = spawn_car(Point2::new(0., 0.), &mut map.rng, &mut map.world).unwrap();
+	map.world.get::<&mut comps::Car>(car).unwrap().attached = true;
+	map.world
+		.get::<&mut comps::Connection>(map.player)
+		.unwrap()
+		.child = Some(car);
+
+	let element = slr_config::to_element(&map.save().unwrap()).unwrap();
+	let save: MapSave = slr_config::from_element(&element, None).unwrap();
+	let mut loaded = Map::load(save).unwrap();
+	assert_eq!(loaded.tick, map.tick);
+	assert_eq!(loaded.target_score, map.target_score);
+	let child = loaded
+		.world
+		.get::<&comps::Connection>(loaded.player)
+		.unwrap()
+		.child
+		.unwrap();
+	assert!(loaded.world.get::<&comps::Car>(child).unwrap().attached);
+
+	for _ in 0..300
+	{
+		map.logic(&input).unwrap();
+		loaded.logic(&input).unwrap();
+	}
+	assert_eq!(loaded.day, map.day);
+	assert_eq!(
+		loaded
+			.world
+			.get::<&comps::Position>(loaded.player)
+			.unwrap()
+			.pos,
+		map.world.get::<&comps::Position>(map.player).unwrap().pos
+	);
+}
+
+#[test]
+fn test_train_springs()
+{
+	let run = |num_cars| {
+		let mut map = test_map(Gravity::None, vec![]);
+		let start = Point2::new(WIDTH / 2., HEIGHT / 4.);
+		map.world
+			.get::<&mut comps::Position>(map.player)
+			.unwrap()
+			.pos = start;
+		map.world
+			.get::<&mut comps::Velocity>(map.player)
+			.unwrap()
+			.pos = Vector2::new(30., 0.);
+		let mut tail = map.player;
+		for i in 0..num_cars
+		{
+			let pos = start - Vector2::new(TRAIN_LINK_LENGTH * (i + 1) as f32, 0.);
+			let car = spawn_car(pos, &mut map.rng, &mut map.world).unwrap();
+			map.world.get::<&mut comps::Car>(car).unwrap().attached = true;
+			map.world.get::<&mut comps::Connection>(tail).unwrap().child = Some(car);
+			tail = car;
+		}
+		for _ in 0..30
+		{
+			map.logic(&Input::default()).unwrap();
+		}
+
+		let mut positions = vec![];
+		let mut cur = Some(map.player);
+		while let Some(e) = cur
+		{
+			positions.push(map.world.get::<&comps::Position>(e).unwrap().pos);
+			cur = map.world.get::<&comps::Connection>(e).unwrap().child;
+		}
+		let vel = map.world.get::<&comps::Velocity>(map.player).unwrap().pos;
+		(vel, positions)
+	};
+
+	let (free_vel, _) = run(0);
+	let (train_vel, positions) = run(5);
+	assert_eq!(positions.len(), 6);
+	for link in positions.windows(2)
+	{
+		let d = (link[0] - link[1]).norm();
+		assert!(d > TRAIN_LINK_LENGTH / 2. && d < 2. * TRAIN_LINK_LENGTH);
+	}
+	// The train drags on the ship.
+	assert!(train_vel.x < free_vel.x);
+}
+
+#[test]
+fn test_cell_collide()
+{
+	let mut rng = StdRng::seed_from_u64(0);
+	let mut names = vec![];
+	let cell = loop
+	{
+		let cell = MapCell::new(&mut names, &mut rng);
+		if let Gravity::Down(_) = cell.gravity
+		{
+			break cell;
+		}
+	};
+	assert!(cell.collide(Point2::new(WIDTH / 2., 0.), 16.).is_none());
+	assert!(cell
+		.collide(Point2::new(WIDTH / 2., HEIGHT - 1.), 16.)
+		.is_some());
+
+	// Moving straight through the ground in a single tick.
+	let start = Point2::new(WIDTH / 2., 0.);
+	let end = Point2::new(WIDTH / 2., 2. * HEIGHT);
+	assert!(cell.collide(end, 16.).is_none());
+	let (toi, (_, _, ground_point)) = cell.sweep(start, end, 16.).unwrap();
+	assert!(toi > 0. && toi < 1.);
+	let contact_pos = start + (end - start) * toi;
+	assert!(((contact_pos - ground_point).norm() - 16.).abs() < 1e-2);
+	assert!(cell.sweep(start, start, 16.).is_none());
+
+	// Grazing the end of a lone segment.
+	let mut cell = cell;
+	cell.ground = vec![(0., 100.), (100., 100.)];
+	let (toi, (_, _, ground_point)) = cell
+		.sweep(Point2::new(110., 0.), Point2::new(110., 200.), 16.)
+		.unwrap();
+	assert_eq!(ground_point, Point2::new(100., 100.));
+	assert!((toi - (100. - (16f32.powi(2) - 10f32.powi(2)).sqrt()) / 200.).abs() < 1e-4);
+}
+
+#[test]
+fn test_gravity_field()
+{
+	let center = Point2::new(WIDTH / 2., HEIGHT / 2.);
+	let mut field = GravityField::new(Gravity::Center(20.), center);
+	let pull = field.pull(Point2::new(0., HEIGHT / 2.));
+	assert!((pull - Vector2::new(20., 0.)).norm() < 1e-3);
+
+	// Moons pull hard up close, and weakly far away.
+	let moon = Point2::new(100., 100.);
+	field.attractors.push(Attractor {
+		pos: moon,
+		strength: 20.,
+		falloff: Some(10.),
+	});
+	let near = field.attractors[1].pull(moon + Vector2::new(10., 0.));
+	assert!((near - Vector2::new(-20., 0.)).norm() < 1e-3);
+	// Inside the moon the pull fades out instead of flipping at the centre.
+	let inside = field.attractors[1].pull(moon + Vector2::new(5., 0.));
+	assert!((inside - Vector2::new(-10., 0.)).norm() < 1e-3);
+	assert_eq!(field.attractors[1].pull(moon), Vector2::new(0., 0.));
+	let far = field.attractors[1].pull(moon + Vector2::new(100., 0.));
+	assert!((far - Vector2::new(-0.2, 0.)).norm() < 1e-3);
+
+	field.winds.push(Wind {
+		min: Point2::new(0., 0.),
+		max: Point2::new(WIDTH, 50.),
+		accel: Vector2::new(10., 0.),
+	});
+	assert_eq!(field.wind(Point2::new(10., 10.)), Vector2::new(10., 0.));
+	assert_eq!(field.wind(Point2::new(10., 60.)), Vector2::new(0., 0.));
+
+	let mut rng = StdRng::seed_from_u64(0);
+	let mut cell = MapCell::new(&mut vec![], &mut rng);
+	cell.gravity = Gravity::Down(10.);
+	cell.ground = (0..96).map(|i| (i as f32 * WIDTH / 95., 300.)).collect();
+	cell.field = GravityField::new(cell.gravity, cell.center);
+	cell.field.drag = 1.;
+	let vel = Vector2::new(0., 20.);
+	// Between two ground points, so the altitude is measured to the segment.
+	let x = (cell.ground[48].0 + cell.ground[49].0) / 2.;
+	let accel = cell.acceleration(Point2::new(x, 300. - ATMOSPHERE_HEIGHT / 2.), vel);
+	assert!((accel - Vector2::new(0., 0.)).norm() < 1e-3);
+	let accel = cell.acceleration(Point2::new(x, 100.), vel);
+	assert_eq!(accel, Vector2::new(0., 10.));
+
+	// A second attractor tilts what counts as level ground.
+	let pos = Point2::new(WIDTH / 2., 290.);
+	assert!((cell.collide(pos, 16.).unwrap().0 - 1.).abs() < 1e-3);
+	cell.field.attractors.push(Attractor {
+		pos: Point2::new(WIDTH, 290.),
+		strength: 10.,
+		falloff: None,
+	});
+	assert!(cell.collide(pos, 16.).unwrap().0 < 0.9);
+}
+
+#[test]
+fn test_sector_layout()
+{
+	let mut layout = SectorLayout {
+		width: 4,
+		height: 3,
+		topology: Topology::Torus,
+	};
+	let corner = Point2::new(3, 2);
+	assert_eq!(layout.neighbour(corner, 0), Some(Point2::new(0, 2)));
+	assert_eq!(layout.neighbour(corner, 3), Some(Point2::new(3, 0)));
+	assert_eq!(layout.cell_pos(layout.cell_idx(corner)), corner);
+
+	layout.topology = Topology::Cylinder;
+	assert_eq!(layout.neighbour(corner, 0), Some(Point2::new(0, 2)));
+	assert_eq!(layout.neighbour(corner, 3), None);
+	assert_eq!(layout.neighbour(corner, 1), Some(Point2::new(3, 1)));
+
+	layout.topology = Topology::Bounded;
+	assert_eq!(layout.neighbour(corner, 0), None);
+	assert_eq!(layout.neighbour(corner, 2), Some(Point2::new(2, 2)));
+
+	layout.topology = Topology::Wormholes;
+	let map = Map::new(
+		0,
+		layout,
+		Difficulty::default(),
+		parts::HullDef::default(),
+		parts::EngineDef::default(),
+	)
+	.unwrap();
+	assert_eq!(map.cells.len(), 12);
+	assert!(!map.wormholes.is_empty());
+	for wormhole in &map.wormholes
+	{
+		assert_eq!(layout.neighbour(wormhole.from, wormhole.dir), None);
+		assert_eq!(
+			map.neighbour(wormhole.from, wormhole.dir),
+			Some(wormhole.to)
+		);
+		assert!(map
+			.wormholes
+			.iter()
+			.any(|w| w.from == wormhole.to && w.to == wormhole.from));
+	}
+}
+
+#[test]
+fn test_route()
+{
+	let mut layout = SectorLayout {
+		width: 7,
+		height: 5,
+		topology: Topology::Torus,
+	};
+	let mut map = Map::new(
+		0,
+		layout,
+		Difficulty::default(),
+		parts::HullDef::default(),
+		parts::EngineDef::default(),
+	)
+	.unwrap();
+	map.cell_pos = Point2::new(0, 0);
+	// Wraps around both edges.
+	let to = Point2::new(6, 4);
+	let route = map.route(to);
+	assert_eq!(route.len(), 3);
+	assert_eq!(route[0], map.cell_pos);
+	assert_eq!(route[2], to);
+	for step in route.windows(2)
+	{
+		assert!(map.exit_dir(step[0], step[1]).is_some());
+	}
+	assert_eq!(map.route(map.cell_pos), vec![map.cell_pos]);
+
+	layout.topology = Topology::Bounded;
+	map.layout = layout;
+	assert_eq!(map.route(to).len(), 11);
+	assert_eq!(map.exit_dir(map.cell_pos, to), None);
+
+	let needy = map.neediest_cell().unwrap();
+	assert_ne!(needy, map.cell_pos);
+	let needy_cell = &map.cells[layout.cell_idx(needy)];
+	assert!(needy_cell.population > 0);
+	assert!(map.cells.iter().all(|c| c.population == 0
+		|| c.population - c.infected >= needy_cell.population - needy_cell.infected));
+}
+
+#[test]
+fn test_difficulty()
+{
+	let difficulty = Difficulty::preset(crate::difficulty::DifficultyLevel::Hard);
+	let mut map = Map::new(
+		0,
+		SectorLayout::default(),
+		difficulty,
+		parts::HullDef::default(),
+		parts::EngineDef::default(),
+	)
+	.unwrap();
+	assert_eq!(map.strength, difficulty.strength(1));
+	map.world.despawn(map.player).unwrap();
+	let events = map
+		.logic(&Input {
+			left: false,
+			right: false,
+			thrust: false,
+		})
+		.unwrap();
+	assert!(events.contains(&GameEvent::PlayerRespawned {
+		score: -difficulty.crash_penalty
+	}));
+	assert_eq!(map.target_score, -difficulty.crash_penalty);
+	assert_eq!(difficulty.research_threshold(500), 600);
+}
+
+#[test]
+fn test_epidemic()
+{
+	let mut map = Map::new(
+		0,
+		SectorLayout::default(),
+		Difficulty::default(),
+		parts::HullDef::default(),
+		parts::EngineDef::default(),
+	)
+	.unwrap();
+	for cell in &mut map.cells
+	{
+		cell.population = 9;
+	}
+	map.cells[0].infected = 5;
+	let start_pop = get_total_pop(&map.cells);
+
+	let mut events = vec![];
+	for _ in 0..10
+	{
+		map.spread_disease(&mut events);
+		for cell in &map.cells
+		{
+			assert!(cell.infected >= 0 && cell.recovered >= 0 && cell.susceptible() >= 0);
+		}
+	}
+	assert!(get_total_pop(&map.cells) < start_pop);
+	let neighbour = map
+		.layout
+		.cell_idx(map.neighbour(Point2::new(0, 0), 0).unwrap());
+	assert!(map.cells[neighbour].infected + map.cells[neighbour].recovered > 0);
+	let infected_events = |cell| {
+		events
+			.iter()
+			.filter(
+				|e| matches!(e, GameEvent::PlanetInfected { cell: c, wiped_out: false } if *c == cell),
+			)
+			.count()
+	};
+	// Cell 0 was sick from the start, so only the newly infected neighbour is reported.
+	assert_eq!(infected_events(0), 0);
+	assert_eq!(infected_events(neighbour), 1);
+}
+
+#[test]
+fn test_contracts()
+{
+	let mut map = Map::new(
+		0,
+		SectorLayout::default(),
+		Difficulty::default(),
+		parts::HullDef::default(),
+		parts::EngineDef::default(),
+	)
+	.unwrap();
+	let mut events = vec![];
+	for _ in 0..1000
+	{
+		if !map.contracts.is_empty()
+		{
+			break;
+		}
+		map.update_contracts(&mut events);
+	}
+	assert!(!map.contracts.is_empty(), "No contract was posted");
+	let contract = map.contracts[0];
+	assert_eq!(events, vec![GameEvent::ContractPosted(contract)]);
+	assert!(map.cells[contract.cell].demand.contains(&contract.cargo));
+
+	let pop = map.cells[contract.cell].population;
+	map.day = contract.deadline + 1;
+	events.clear();
+	map.update_contracts(&mut events);
+	assert_eq!(events[0], GameEvent::ContractFailed(contract));
+	assert_eq!(map.cells[contract.cell].population, pop - 1);
+	assert!(!map.contracts.contains(&contract));
+}
+
+#[test]
+fn test_fuel()
+{
+	let mut map = Map::new(
+		0,
+		SectorLayout::default(),
+		Difficulty::default(),
+		parts::HullDef::default(),
+		parts::EngineDef {
+			fuel_use: 2.,
+			..parts::EngineDef::default()
+		},
+	)
+	.unwrap();
+	let input = Input {
+		left: false,
+		right: false,
+		thrust: true,
+	};
+	map.logic(&input).unwrap();
+	let fuel = *map.world.get::<&comps::Fuel>(map.player).unwrap();
+	assert_eq!(fuel.fuel, MAX_FUEL - 2. * FUEL_USE * utils::DT);
+
+	map.world.get::<&mut comps::Fuel>(map.player).unwrap().fuel = 0.;
+	let vel = map.world.get::<&comps::Velocity>(map.player).unwrap().pos;
+	map.logic(&input).unwrap();
+	assert!(!map.world.get::<&comps::Engine>(map.player).unwrap().on);
+	let new_vel = map.world.get::<&comps::Velocity>(map.player).unwrap().pos;
+	// Only gravity acts on the ship now.
+	assert!((new_vel - vel).dot(&Vector2::new(0., -1.)) <= 0.);
+}
+
+#[test]
+fn test_landing_pad()
+{
+	let mut rng = StdRng::seed_from_u64(0);
+	let mut names = vec![];
+	let mut num_planets = 0;
+	while num_planets < 10
+	{
+		let cell = MapCell::new(&mut names, &mut rng);
+		if let Gravity::None = cell.gravity
+		{
+			continue;
+		}
+		num_planets += 1;
+		let (start, end) = cell.landing_pad.unwrap();
+		let (x, y) = cell.ground[(start + end) / 2];
+		assert!(cell.on_landing_pad(Point2::new(x, y)));
+		assert!(!cell.on_landing_pad(Point2::new(x, y - 100.)));
+	}
+
+	let mut ground: Vec<_> = (0..96).map(|i| (i as f32 * WIDTH / 95., 300.)).collect();
+	ground.push((WIDTH, HEIGHT));
+	ground.push((0., HEIGHT));
+	let mut map = test_map(Gravity::Down(0.), ground);
+	let cell = &mut map.cells[0];
+	cell.population = 5;
+	cell.demand = vec![];
+	cell.landing_pad = Some((0, 48));
+
+	let max_vel = map.difficulty.max_vel;
+	let deliver = |map: &mut Map, x: f32| {
+		let pos = Point2::new(x, 300. - 16.1);
+		*map.world.get::<&mut comps::Position>(map.player).unwrap() = comps::Position {
+			pos: pos,
+			dir: -utils::PI / 2.,
+		};
+		map.world
+			.get::<&mut comps::Velocity>(map.player)
+			.unwrap()
+			.pos = Vector2::new(0., max_vel / 2.);
+		let car = spawn_car(
+			pos - Vector2::new(0., TRAIN_LINK_LENGTH),
+			&mut map.rng,
+			&mut map.world,
+		)
+		.unwrap();
+		map.world.get::<&mut comps::Car>(car).unwrap().attached = true;
+		map.world
+			.get::<&mut comps::Connection>(map.player)
+			.unwrap()
+			.child = Some(car);
+		let mut score = 0;
+		for _ in 0..300
+		{
+			for event in map.logic(&Input::default()).unwrap()
+			{
+				if let GameEvent::SupplyScored { score: s, .. } = event
+				{
+					score += s;
+				}
+			}
+		}
+		score
+	};
+	let on_pad = deliver(&mut map, WIDTH / 4.);
+	let off_pad = deliver(&mut map, 3. * WIDTH / 4.);
+	assert!(off_pad > 0);
+	assert_eq!(on_pad as f32, LANDING_PAD_BONUS * off_pad as f32);
+}
+
+#[test]
+fn test_hull()
+{
+	let mut map = test_map(
+		Gravity::Down(0.),
+		vec![(0., 300.), (WIDTH, 300.), (WIDTH, HEIGHT), (0., HEIGHT)],
+	);
+
+	let input = Input {
+		left: false,
+		right: false,
+		thrust: false,
+	};
+	let max_vel = map.difficulty.max_vel;
+	let land = |map: &mut Map, speed: f32| {
+		*map.world.get::<&mut comps::Position>(map.player).unwrap() = comps::Position {
+			pos: Point2::new(WIDTH / 2., 300. - 16.1),
+			dir: -utils::PI / 2.,
+		};
+		map.world
+			.get::<&mut comps::Velocity>(map.player)
+			.unwrap()
+			.pos = Vector2::new(0., speed);
+		map.logic(&input).unwrap()
+	};
+
+	// A gentle landing is free.
+	land(&mut map, max_vel / 2.);
+	assert_eq!(
+		map.world.get::<&comps::Hull>(map.player).unwrap().health,
+		SHIP_HULL
+	);
+
+	// A hard landing damages the ship.
+	let events = land(&mut map, 1.2 * max_vel);
+	let health = map.world.get::<&comps::Hull>(map.player).unwrap().health;
+	assert!(health > 0. && health < SHIP_HULL);
+	assert!(events
+		.iter()
+		.any(|e| matches!(e, GameEvent::ShipDamaged { .. })));
+
+	// A crash destroys it.
+	let events = land(&mut map, 2. * max_vel);
+	assert!(!map.world.contains(map.player));
+	assert!(events
+		.iter()
+		.any(|e| matches!(e, GameEvent::Explosion { .. })));
+}
+
+#[test]
+fn test_trajectory()
+{
+	let mut map = test_map(
+		Gravity::Down(10.),
+		vec![(0., 300.), (WIDTH, 300.), (WIDTH, HEIGHT), (0., HEIGHT)],
+	);
+
+	let max_vel = map.difficulty.max_vel;
+	let predict = |map: &mut Map, vel: Vector2<f32>| {
+		*map.world.get::<&mut comps::Position>(map.player).unwrap() = comps::Position {
+			pos: Point2::new(WIDTH / 2., 270.),
+			dir: -utils::PI / 2.,
+		};
+		map.world
+			.get::<&mut comps::Velocity>(map.player)
+			.unwrap()
+			.pos = vel;
+		map.predict_trajectory().unwrap()
+	};
+
+	let trajectory = predict(&mut map, Vector2::new(10., 0.));
+	let (pos, landing) = trajectory.impact.unwrap();
+	assert_eq!(landing, Landing::Safe);
+	assert!((pos.y - (300. - 16.)).abs() < 1.);
+	assert!(pos.x > WIDTH / 2.);
+	// The ship ends up where it was predicted to.
+	let input = Input {
+		left: false,
+		right: false,
+		thrust: false,
+	};
+	for _ in 0..trajectory.points.len()
+	{
+		map.logic(&input).unwrap();
+	}
+	let end = map.world.get::<&comps::Position>(map.player).unwrap().pos;
+	assert!((end - pos).norm() < 1.);
+
+	let landing =
+		|map: &mut Map, speed: f32| predict(map, Vector2::new(0., speed)).impact.unwrap().1;
+	// Gravity speeds it up past the safe speed before it lands.
+	assert_eq!(landing(&mut map, max_vel), Landing::TooFast);
+	assert_eq!(landing(&mut map, 2. * max_vel), Landing::Crash);
+
+	// Flying out of the cell.
+	assert!(predict(&mut map, Vector2::new(0., -200.)).impact.is_none());
+}
+
+#[test]
+fn test_upgrades()
+{
+	let mut map = test_map(
+		Gravity::Down(0.),
+		vec![(0., 300.), (WIDTH, 300.), (WIDTH, HEIGHT), (0., HEIGHT)],
+	);
+	map.cells[0].population = 5;
+	map.score = 2500;
+	map.target_score = 2500;
+
+	let input = Input {
+		left: false,
+		right: false,
+		thrust: false,
+	};
+	let set_pos = |map: &mut Map, y: f32| {
+		*map.world.get::<&mut comps::Position>(map.player).unwrap() = comps::Position {
+			pos: Point2::new(WIDTH / 2., y),
+			dir: -utils::PI / 2.,
+		};
+		map.world
+			.get::<&mut comps::Velocity>(map.player)
+			.unwrap()
+			.pos = Vector2::new(0., 10.);
+	};
+
+	// The shop is closed in flight.
+	set_pos(&mut map, 100.);
+	map.logic(&input).unwrap();
+	assert!(!map.can_shop());
+	assert!(!map.buy_upgrade(Upgrade::Landing));
+
+	set_pos(&mut map, 300. - 16.1);
+	map.logic(&input).unwrap();
+	assert!(map.can_shop());
+
+	let max_vel = map.max_vel();
+	assert_eq!(map.upgrade_cost(Upgrade::Landing), Some(1000));
+	assert!(map.buy_upgrade(Upgrade::Landing));
+	assert_eq!(map.target_score, 1500);
+	assert_eq!(map.upgrades.level(Upgrade::Landing), 1);
+	assert!(map.max_vel() > max_vel);
+
+	// Too expensive.
+	assert_eq!(map.upgrade_cost(Upgrade::Landing), Some(2000));
+	assert!(!map.buy_upgrade(Upgrade::Landing));
+
+	assert!(map.buy_upgrade(Upgrade::Hull));
+	assert_eq!(map.target_score, 500);
+	let hull = *map.world.get::<&comps::Hull>(map.player).unwrap();
+	assert!(hull.max_health > SHIP_HULL);
+	assert_eq!(hull.health, hull.max_health);
+
+	// Upgrades survive saving.
+	let element = slr_config::to_element(&map.save().unwrap()).unwrap();
+	let save: MapSave = slr_config::from_element(&element, None).unwrap();
+	let loaded = Map::load(save).unwrap();
+	assert_eq!(loaded.upgrades, map.upgrades);
+}
+
+#[test]
+fn test_hazards()
+{
+	let mut map = test_map(Gravity::None, vec![]);
+
+	// More hazards show up as the disease gets stronger.
+	let cell = map.cells[0].clone();
+	let count_hazards = |strength| {
+		let mut world = hecs::World::new();
+		cell.spawn_objects(strength, &mut StdRng::seed_from_u64(0), &mut world)
+			.unwrap();
+		world.query_mut::<&comps::Hazard>().into_iter().count() as i32
+	};
+	assert_eq!(count_hazards(0), 0);
+	assert!(count_hazards(1) < count_hazards(3));
+
+	let pos = Point2::new(WIDTH / 2., HEIGHT / 2.);
+	*map.world.get::<&mut comps::Position>(map.player).unwrap() =
+		comps::Position { pos: pos, dir: 0. };
+	let hazard = spawn_hazard(pos, &mut map.rng, &mut map.world).unwrap();
+	*map.world.get::<&mut comps::Position>(hazard).unwrap() = comps::Position {
+		pos: pos + Vector2::new(-20., 0.),
+		dir: 0.,
+	};
+	map.world.get::<&mut comps::Velocity>(hazard).unwrap().pos = Vector2::new(10., 0.);
+
+	let input = Input {
+		left: false,
+		right: false,
+		thrust: false,
+	};
+	let events = map.logic(&input).unwrap();
+	assert!(events
+		.iter()
+		.any(|e| matches!(e, GameEvent::ShipDamaged { .. })));
+	let health = map.world.get::<&comps::Hull>(map.player).unwrap().health;
+	assert!(health < SHIP_HULL);
+	// The ship got knocked away, so it isn't hit again.
+	assert!(map.world.get::<&comps::Velocity>(map.player).unwrap().pos.x > 0.);
+	map.logic(&input).unwrap();
+	assert_eq!(
+		map.world.get::<&comps::Hull>(map.player).unwrap().health,
+		health
+	);
+
+	// A car crushed between two hazards is only lost once.
+	let car_pos = Point2::new(100., 100.);
+	let car = spawn_car(car_pos, &mut map.rng, &mut map.world).unwrap();
+	map.world.get::<&mut comps::Hull>(car).unwrap().health = 1.;
+	for side in [-1., 1.]
+	{
+		let hazard = spawn_hazard(car_pos, &mut map.rng, &mut map.world).unwrap();
+		*map.world.get::<&mut comps::Position>(hazard).unwrap() = comps::Position {
+			pos: car_pos + Vector2::new(15. * side, 0.),
+			dir: 0.,
+		};
+		map.world.get::<&mut comps::Velocity>(hazard).unwrap().pos = Vector2::new(-10. * side, 0.);
+	}
+	let events = map.logic(&input).unwrap();
+	assert_eq!(
+		events
+			.iter()
+			.filter(|e| matches!(e, GameEvent::CarLost))
+			.count(),
+		1
+	);
+}
+
+#[test]
+fn test_derelicts()
+{
+	// A wall between the derelict and the player.
+	let mut map = test_map(
+		Gravity::Down(20.),
+		vec![
+			(0., 440.),
+			(300., 440.),
+			(300., 200.),
+			(340., 200.),
+			(340., 440.),
+			(WIDTH, 440.),
+			(WIDTH, HEIGHT),
+			(0., HEIGHT),
+		],
+	);
+	map.world
+		.remove_one::<comps::AffectedByGravity>(map.player)
+		.unwrap();
+	*map.world.get::<&mut comps::Position>(map.player).unwrap() = comps::Position {
+		pos: Point2::new(500., 380.),
+		dir: 0.,
+	};
+	let derelict = spawn_derelict(Point2::new(140., 380.), &mut map.world).unwrap();
+
+	let input = Input {
+		left: false,
+		right: false,
+		thrust: false,
+	};
+	let mut damaged = false;
+	for _ in 0..3000
+	{
+		let events = map.logic(&input).unwrap();
+		damaged |= events
+			.iter()
+			.any(|e| matches!(e, GameEvent::ShipDamaged { .. }));
+		if !map.world.contains(derelict)
+		{
+			break;
+		}
+	}
+	// It flew over the wall and rammed the player.
+	assert!(!map.world.contains(derelict));
+	assert!(damaged);
+}
@@ -0,0 +1,61 @@
+use crate::{sim, utils};
+use serde_derive::{Deserialize, Serialize};
+
+/// Run statistics shown on the results screens.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Stats
+{
+	pub num_crashes: i32,
+	pub max_train: i32,
+	pub num_cars_lost: i32,
+	pub num_cars_delivered: i32,
+}
+
+impl Stats
+{
+	pub fn new() -> Self
+	{
+		Self::default()
+	}
+
+	pub fn on_event(&mut self, event: &sim::GameEvent)
+	{
+		match event
+		{
+			sim::GameEvent::PlayerRespawned { .. } => self.num_crashes += 1,
+			sim::GameEvent::CarLost => self.num_cars_lost += 1,
+			sim::GameEvent::CarDelivered => self.num_cars_delivered += 1,
+			sim::GameEvent::TrainDelivered { num_cars } =>
+			{
+				self.max_train = utils::max(self.max_train, *num_cars);
+			}
+			_ => (),
+		}
+	}
+}
+
+#[test]
+fn test_stats()
+{
+	let mut stats = Stats::new();
+	for event in [
+		sim::GameEvent::CarDelivered,
+		sim::GameEvent::CarDelivered,
+		sim::GameEvent::TrainDelivered { num_cars: 2 },
+		sim::GameEvent::CarLost,
+		sim::GameEvent::PlayerRespawned { score: -1000 },
+		sim::GameEvent::TrainDelivered { num_cars: 1 },
+	]
+	{
+		stats.on_event(&event);
+	}
+	assert_eq!(
+		stats,
+		Stats {
+			num_crashes: 1,
+			max_train: 2,
+			num_cars_lost: 1,
+			num_cars_delivered: 2,
+		}
+	);
+}
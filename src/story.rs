@@ -0,0 +1,67 @@
+use crate::error::Result;
+use crate::utils;
+use serde_derive::{Deserialize, Serialize};
+
+pub const STORY_FILE: &str = "data/story.cfg";
+
+/// What makes a story beat fire. Thresholds fire once, on the day they are first reached.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Trigger
+{
+	Day(i32),
+	Research(i32),
+	PopulationBelow(i32),
+	PlanetLost,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum StoryEffect
+{
+	/// Text to show, with `{thrust}`, `{left}`, `{right}`, `{show_map}` and `{sector}`
+	/// placeholders.
+	Message(String),
+	Sound(String),
+	Strength(i32),
+	/// Wins the run when the next cell is entered.
+	Victory,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StoryBeat
+{
+	pub trigger: Trigger,
+	pub effects: Vec<StoryEffect>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Story
+{
+	pub beats: Vec<StoryBeat>,
+}
+
+impl Story
+{
+	pub fn load(file: &str) -> Result<Self>
+	{
+		utils::load_config(file).map_err(|e| e.context(format!("Couldn't load '{}'", file)))
+	}
+
+	/// The effects of all the beats whose trigger matches, in script order.
+	pub fn effects(&self, fires: impl Fn(&Trigger) -> bool) -> Vec<StoryEffect>
+	{
+		self.beats
+			.iter()
+			.filter(|b| fires(&b.trigger))
+			.flat_map(|b| b.effects.iter().cloned())
+			.collect()
+	}
+}
+
+#[test]
+fn test_story_file()
+{
+	let story = Story::load(STORY_FILE).unwrap();
+	let effects = story.effects(|t| *t == Trigger::Research(1000));
+	assert!(effects.contains(&StoryEffect::Victory));
+	assert!(story.beats.iter().any(|b| b.trigger == Trigger::Day(0)));
+}
@@ -3,7 +3,7 @@
 // TODO: Fallbile screen creation.
 
 use crate::error::Result;
-use crate::{components, controls, game_state, utils};
+use crate::{components, controls, difficulty, game_state, parts, sim, utils};
 
 use allegro::*;
 use allegro_font::*;
@@ -26,11 +26,13 @@ pub enum Action
 	SelectMe,
 	MainMenu,
 	Start,
+	Continue,
 	Quit,
 	Back,
 	Forward(fn(&mut game_state::GameState) -> SubScreen),
 	ToggleFullscreen,
 	ToggleFracScale,
+	ToggleTrajectory,
 	ChangeInput(controls::Action, usize),
 	MouseSensitivity(f32),
 	UiScale(f32),
@@ -39,6 +41,9 @@ pub enum Action
 	CameraSpeed(i32),
 	PlayerShip(i32),
 	PlayerEngine(i32),
+	Difficulty(usize),
+	BuyUpgrade(sim::Upgrade),
+	EditSeed,
 }
 
 #[derive(Clone)]
@@ -803,22 +808,35 @@ impl WidgetList
 pub struct AppearanceMenu
 {
 	widgets: WidgetList,
+	hulls: Vec<parts::HullDef>,
+	engines: Vec<parts::EngineDef>,
 }
 
 impl AppearanceMenu
 {
 	pub fn new(state: &mut game_state::GameState) -> Result<Self>
 	{
-		for i in 0..5
+		let mut hulls = vec![];
+		let mut engines = vec![];
+		for i in 0..parts::NUM_PARTS
 		{
-			state.cache_sprite(&format!("data/ship{}.cfg", i + 1))?;
-			state.cache_sprite(&format!("data/engine{}.cfg", i + 1))?;
+			let hull = parts::HullDef::load(&parts::hull_file(i))?;
+			let engine = parts::EngineDef::load(&parts::engine_file(i))?;
+			state.cache_sprite(&hull.sprite)?;
+			state.cache_sprite(&engine.sprite)?;
+			hulls.push(hull);
+			engines.push(engine);
 		}
 		let w = BUTTON_WIDTH;
 		let h = BUTTON_HEIGHT;
 
 		let widgets = WidgetList::new(&[
-			&[Widget::Button(Button::new(w, h, "Start", Action::Start))],
+			&[Widget::Button(Button::new(
+				w,
+				h,
+				"Next",
+				Action::Forward(|s| SubScreen::NewGameMenu(NewGameMenu::new(s))),
+			))],
 			&[Widget::Label(Label::new(w, h, "Hull"))],
 			&[Widget::Slider(Slider::new(
 				2. * w,
@@ -841,7 +859,11 @@ impl AppearanceMenu
 			))],
 			&[Widget::Button(Button::new(w, h, "Back", Action::Back))],
 		]);
-		let mut res = Self { widgets: widgets };
+		let mut res = Self {
+			widgets: widgets,
+			hulls: hulls,
+			engines: engines,
+		};
 		res.resize(state);
 		Ok(res)
 	}
@@ -852,20 +874,57 @@ impl AppearanceMenu
 		let cx = state.buffer_width() / 2.;
 		let cy = state.buffer_height() / 2.;
 
-		let sprite = state.get_sprite(&state.player_ship()).unwrap();
+		let hull = &self.hulls[state.options.player_ship as usize];
+		let engine = &self.engines[state.options.player_engine as usize];
+		let sprite = state.get_sprite(&hull.sprite).unwrap();
 		sprite.draw(
 			Point2::new(cx, cy - 128.),
 			sprite.get_variant(state.time()),
 			Color::from_rgb_f(1., 1., 1.),
 			state,
 		);
-		let sprite = state.get_sprite(&state.player_engine()).unwrap();
+		let sprite = state.get_sprite(&engine.sprite).unwrap();
 		sprite.draw(
 			Point2::new(cx, cy - 128.),
 			sprite.get_variant(state.time()),
 			Color::from_rgb_f(1., 1., 1.),
 			state,
 		);
+
+		let lh = state.ui_font().get_line_height() as f32;
+		let hull_stats = [
+			hull.name.clone(),
+			format!("Size: {}", hull.size),
+			format!("Mass: {:.1}", hull.mass),
+			format!("Turn: {:.1}", hull.turn_rate),
+		];
+		let engine_stats = [
+			engine.name.clone(),
+			format!("Thrust: {}", engine.thrust),
+			format!("Fuel use: {:.0}%", 100. * engine.fuel_use),
+			format!("Accel: {:.0}", engine.thrust / hull.mass),
+		];
+		for (i, (hull_stat, engine_stat)) in hull_stats.iter().zip(&engine_stats).enumerate()
+		{
+			let y = (cy - 128. + lh * (i as f32 - 2.)).round();
+			let color = if i == 0 { SELECTED } else { LABEL };
+			state.core.draw_text(
+				state.ui_font(),
+				color,
+				(cx - 48.).round(),
+				y,
+				FontAlign::Right,
+				hull_stat,
+			);
+			state.core.draw_text(
+				state.ui_font(),
+				color,
+				(cx + 48.).round(),
+				y,
+				FontAlign::Left,
+				engine_stat,
+			);
+		}
 	}
 
 	pub fn input(&mut self, state: &mut game_state::GameState, event: &Event) -> Option<Action>
@@ -907,6 +966,151 @@ impl AppearanceMenu
 	}
 }
 
+pub struct NewGameMenu
+{
+	widgets: WidgetList,
+	accepting_input: bool,
+	seed_text: String,
+}
+
+impl NewGameMenu
+{
+	pub fn new(state: &game_state::GameState) -> Self
+	{
+		let w = BUTTON_WIDTH;
+		let h = BUTTON_HEIGHT;
+
+		let widgets = WidgetList::new(&[
+			&[Widget::Button(Button::new(w, h, "Start", Action::Start))],
+			&[
+				Widget::Label(Label::new(w, h, "Difficulty")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					difficulty::DifficultyLevel::ALL
+						.iter()
+						.position(|&l| l == state.options.difficulty)
+						.unwrap(),
+					difficulty::DifficultyLevel::ALL
+						.iter()
+						.map(|l| l.name().to_string())
+						.collect(),
+					Action::Difficulty,
+				)),
+			],
+			&[
+				Widget::Label(Label::new(w, h, "Seed")),
+				Widget::Button(Button::new(w, h, "Random", Action::EditSeed)),
+			],
+			&[Widget::Button(Button::new(w, h, "Back", Action::Back))],
+		]);
+		let mut res = Self {
+			widgets: widgets,
+			accepting_input: false,
+			seed_text: state.seed.map(|s| s.to_string()).unwrap_or_default(),
+		};
+		res.update_seed_button();
+		res.resize(state);
+		res
+	}
+
+	fn update_seed_button(&mut self)
+	{
+		for widget_row in &mut self.widgets.widgets
+		{
+			for widget in widget_row
+			{
+				if let Widget::Button(b) = widget
+				{
+					if b.action == Action::EditSeed
+					{
+						b.text = if self.accepting_input
+						{
+							format!("{}_", self.seed_text)
+						}
+						else if self.seed_text.is_empty()
+						{
+							"Random".into()
+						}
+						else
+						{
+							self.seed_text.clone()
+						};
+					}
+				}
+			}
+		}
+	}
+
+	pub fn draw(&self, state: &game_state::GameState)
+	{
+		self.widgets.draw(state);
+	}
+
+	pub fn input(&mut self, state: &mut game_state::GameState, event: &Event) -> Option<Action>
+	{
+		if self.accepting_input
+		{
+			match event
+			{
+				Event::KeyDown {
+					keycode: KeyCode::Enter | KeyCode::PadEnter,
+					..
+				} =>
+				{
+					state.sfx.play_sound("data/ui2.ogg").unwrap();
+					state.seed = self.seed_text.parse().ok();
+					self.accepting_input = false;
+				}
+				Event::KeyChar {
+					keycode: KeyCode::Backspace,
+					..
+				} =>
+				{
+					self.seed_text.pop();
+				}
+				Event::KeyChar { unichar, .. } =>
+				{
+					// Keep it within the range of u64.
+					if unichar.is_ascii_digit() && self.seed_text.len() < 19
+					{
+						self.seed_text.push(*unichar);
+					}
+				}
+				_ => (),
+			}
+			self.update_seed_button();
+			return None;
+		}
+
+		match self.widgets.input(state, event)
+		{
+			Some(Action::EditSeed) =>
+			{
+				self.accepting_input = true;
+				self.update_seed_button();
+			}
+			Some(Action::Difficulty(i)) =>
+			{
+				state.options.difficulty = difficulty::DifficultyLevel::ALL[i];
+				game_state::save_options(&state.core, &state.options).unwrap();
+			}
+			action => return action,
+		}
+		None
+	}
+
+	pub fn resize(&mut self, state: &game_state::GameState)
+	{
+		let cx = state.buffer_width() / 2.;
+		let cy = state.buffer_height() / 2.;
+
+		self.widgets.pos.x = cx;
+		self.widgets.pos.y = cy;
+		self.widgets.resize(state);
+	}
+}
+
 pub struct MainMenu
 {
 	widgets: WidgetList,
@@ -919,28 +1123,43 @@ impl MainMenu
 		let w = BUTTON_WIDTH;
 		let h = BUTTON_HEIGHT;
 
-		let widgets = WidgetList::new(&[
-			&[Widget::Button(Button::new(
-				w,
-				h,
-				"New Game",
-				Action::Forward(|s| SubScreen::AppearanceMenu(AppearanceMenu::new(s).unwrap())),
-			))],
-			&[Widget::Button(Button::new(
-				w,
-				h,
-				"Controls",
-				Action::Forward(|s| SubScreen::ControlsMenu(ControlsMenu::new(s))),
-			))],
-			&[Widget::Button(Button::new(
+		let mut widgets = vec![];
+		if game_state::save_exists(&state.core)
+		{
+			widgets.push(vec![Widget::Button(Button::new(
 				w,
 				h,
-				"Options",
-				Action::Forward(|s| SubScreen::OptionsMenu(OptionsMenu::new(s))),
-			))],
-			&[Widget::Button(Button::new(w, h, "Quit", Action::Quit))],
-		]);
-		let mut res = Self { widgets: widgets };
+				"Continue",
+				Action::Continue,
+			))]);
+		}
+		widgets.push(vec![Widget::Button(Button::new(
+			w,
+			h,
+			"New Game",
+			Action::Forward(|s| SubScreen::AppearanceMenu(AppearanceMenu::new(s).unwrap())),
+		))]);
+		widgets.push(vec![Widget::Button(Button::new(
+			w,
+			h,
+			"Controls",
+			Action::Forward(|s| SubScreen::ControlsMenu(ControlsMenu::new(s))),
+		))]);
+		widgets.push(vec![Widget::Button(Button::new(
+			w,
+			h,
+			"Options",
+			Action::Forward(|s| SubScreen::OptionsMenu(OptionsMenu::new(s))),
+		))]);
+		widgets.push(vec![Widget::Button(Button::new(
+			w,
+			h,
+			"Quit",
+			Action::Quit,
+		))]);
+		let mut res = Self {
+			widgets: WidgetList::new(&widgets.iter().map(|r| &r[..]).collect::<Vec<_>>()),
+		};
 		res.resize(state);
 		res
 	}
@@ -1170,6 +1389,16 @@ impl OptionsMenu
 					|_| Action::ToggleFracScale,
 				)),
 			],
+			vec![
+				Widget::Label(Label::new(w, h, "Trajectory")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					state.options.show_trajectory as usize,
+					vec!["No".into(), "Yes".into()],
+					|_| Action::ToggleTrajectory,
+				)),
+			],
 			vec![
 				Widget::Label(Label::new(w, h, "Music")),
 				Widget::Slider(Slider::new(
@@ -1239,6 +1468,11 @@ impl OptionsMenu
 					state.options.frac_scale = !state.options.frac_scale;
 					options_changed = true;
 				}
+				Action::ToggleTrajectory =>
+				{
+					state.options.show_trajectory = !state.options.show_trajectory;
+					options_changed = true;
+				}
 				Action::MusicVolume(v) =>
 				{
 					state.options.music_volume = v;
@@ -1329,6 +1563,76 @@ impl InGameMenu
 	}
 }
 
+pub struct ShopMenu
+{
+	widgets: WidgetList,
+}
+
+impl ShopMenu
+{
+	pub fn new(state: &game_state::GameState, map: &sim::Map) -> Self
+	{
+		let w = BUTTON_WIDTH;
+		let h = BUTTON_HEIGHT;
+
+		let mut widgets = vec![vec![Widget::Label(Label::new(
+			w * 2.,
+			h,
+			&format!("Score: {}", map.score),
+		))]];
+		for upgrade in sim::Upgrade::ALL
+		{
+			let label = Widget::Label(Label::new(
+				w,
+				h,
+				&format!("{} {}/3", upgrade.name(), map.upgrades.level(upgrade)),
+			));
+			let buy = match map.upgrade_cost(upgrade)
+			{
+				Some(cost) => Widget::Button(Button::new(
+					w,
+					h,
+					&format!("Buy {}", cost),
+					Action::BuyUpgrade(upgrade),
+				)),
+				None => Widget::Label(Label::new(w, h, "Max")),
+			};
+			widgets.push(vec![label, buy]);
+		}
+		widgets.push(vec![Widget::Button(Button::new(
+			w,
+			h,
+			"Back",
+			Action::Back,
+		))]);
+
+		let mut res = Self {
+			widgets: WidgetList::new(&widgets.iter().map(|r| &r[..]).collect::<Vec<_>>()),
+		};
+		res.resize(state);
+		res
+	}
+
+	pub fn draw(&self, state: &game_state::GameState)
+	{
+		self.widgets.draw(state);
+	}
+
+	pub fn input(&mut self, state: &mut game_state::GameState, event: &Event) -> Option<Action>
+	{
+		self.widgets.input(state, event)
+	}
+
+	pub fn resize(&mut self, state: &game_state::GameState)
+	{
+		let cx = state.buffer_width() / 2.;
+		let cy = state.buffer_height() / 2.;
+		self.widgets.pos.x = cx;
+		self.widgets.pos.y = cy;
+		self.widgets.resize(state);
+	}
+}
+
 pub enum SubScreen
 {
 	MainMenu(MainMenu),
@@ -1336,6 +1640,8 @@ pub enum SubScreen
 	OptionsMenu(OptionsMenu),
 	InGameMenu(InGameMenu),
 	AppearanceMenu(AppearanceMenu),
+	NewGameMenu(NewGameMenu),
+	ShopMenu(ShopMenu),
 }
 
 impl SubScreen
@@ -1349,6 +1655,8 @@ impl SubScreen
 			SubScreen::OptionsMenu(s) => s.draw(state),
 			SubScreen::InGameMenu(s) => s.draw(state),
 			SubScreen::AppearanceMenu(s) => s.draw(state),
+			SubScreen::NewGameMenu(s) => s.draw(state),
+			SubScreen::ShopMenu(s) => s.draw(state),
 		}
 	}
 
@@ -1361,6 +1669,8 @@ impl SubScreen
 			SubScreen::OptionsMenu(s) => s.input(state, event),
 			SubScreen::InGameMenu(s) => s.input(state, event),
 			SubScreen::AppearanceMenu(s) => s.input(state, event),
+			SubScreen::NewGameMenu(s) => s.input(state, event),
+			SubScreen::ShopMenu(s) => s.input(state, event),
 		}
 	}
 
@@ -1373,6 +1683,8 @@ impl SubScreen
 			SubScreen::OptionsMenu(s) => s.resize(state),
 			SubScreen::InGameMenu(s) => s.resize(state),
 			SubScreen::AppearanceMenu(s) => s.resize(state),
+			SubScreen::NewGameMenu(s) => s.resize(state),
+			SubScreen::ShopMenu(s) => s.resize(state),
 		}
 	}
 }